@@ -0,0 +1,24 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Size of the scratch buffer used for formatting messages with the `print-buffer` feature.
+const DEFAULT_PRINT_BUFFER_SIZE: usize = 128;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=RTT_PRINT_BUFFER_SIZE");
+
+    let size = match env::var("RTT_PRINT_BUFFER_SIZE") {
+        Ok(size) => size
+            .parse::<usize>()
+            .expect("RTT_PRINT_BUFFER_SIZE must be a number"),
+        Err(_) => DEFAULT_PRINT_BUFFER_SIZE,
+    };
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(
+        out_dir.join("print_buffer_size.rs"),
+        format!("pub(crate) const PRINT_BUFFER_SIZE: usize = {};\n", size),
+    )
+    .unwrap();
+}
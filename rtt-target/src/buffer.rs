@@ -0,0 +1,66 @@
+use core::mem::MaybeUninit;
+
+/// Memory that can be used as the buffer of a channel.
+///
+/// Implemented for `'static` mutable references to byte slices and arrays, either initialized or
+/// wrapped in [`MaybeUninit`]. The buffer can be given to the `buffer` option of
+/// [`rtt_init`](crate::rtt_init) or to [`ChannelConfig::new`](crate::ChannelConfig::new), for
+/// example to place a channel in a memory region determined at runtime:
+///
+/// ```
+/// let buffer = unsafe { core::slice::from_raw_parts_mut(EXT_SRAM_BASE as *mut u8, 0x1000) };
+///
+/// let channels = rtt_init! {
+///     up: {
+///         0: { buffer: buffer, name: "Terminal" }
+///     }
+/// };
+/// ```
+///
+/// # Safety
+///
+/// Implementations must return a pointer to memory that is valid for reads and writes for the rest
+/// of the program, and that is not accessed through anything else than the channel.
+pub unsafe trait ChannelBuffer {
+    /// Converts the buffer into a raw pointer.
+    fn into_raw(self) -> *mut [u8];
+}
+
+unsafe impl ChannelBuffer for &'static mut [u8] {
+    fn into_raw(self) -> *mut [u8] {
+        self
+    }
+}
+
+unsafe impl<const N: usize> ChannelBuffer for &'static mut [u8; N] {
+    fn into_raw(self) -> *mut [u8] {
+        self
+    }
+}
+
+unsafe impl ChannelBuffer for &'static mut [MaybeUninit<u8>] {
+    fn into_raw(self) -> *mut [u8] {
+        self as *mut [MaybeUninit<u8>] as *mut [u8]
+    }
+}
+
+unsafe impl<const N: usize> ChannelBuffer for &'static mut MaybeUninit<[u8; N]> {
+    fn into_raw(self) -> *mut [u8] {
+        self.as_mut_ptr()
+    }
+}
+
+/// Converts `buffer` into a raw pointer for initializing a channel.
+///
+/// # Panics
+///
+/// Panics if the buffer is too small to hold any data, as one byte is always kept free.
+pub(crate) fn into_raw(buffer: impl ChannelBuffer) -> *mut [u8] {
+    let buffer = buffer.into_raw();
+
+    // Safety: the pointer is valid as guaranteed by the trait
+    let len = unsafe { (&*buffer).len() };
+    assert!(len >= 2, "RTT channel buffer must be at least 2 bytes long");
+
+    buffer
+}
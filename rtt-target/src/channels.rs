@@ -0,0 +1,85 @@
+use crate::rtt::{self, RttChannel};
+use crate::ChannelMode;
+use core::ffi::CStr;
+
+/// Information about a channel in the control block, as returned by [`up_channels`] and
+/// [`down_channels`].
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct ChannelInfo {
+    /// The channel number.
+    pub number: usize,
+
+    /// The name of the channel, if it has one.
+    pub name: Option<&'static CStr>,
+
+    /// The size of the channel buffer in bytes.
+    pub size: usize,
+
+    /// The current mode of the channel. This is only meaningful for up channels.
+    pub mode: ChannelMode,
+
+    /// True if the channel has been initialized. Channels pre-allocated without a size in
+    /// [`rtt_init`](crate::rtt_init) are uninitialized until they are claimed.
+    pub initialized: bool,
+}
+
+impl ChannelInfo {
+    /// Returns true if the channel has been initialized and its name is `name`.
+    pub fn has_name(&self, name: &str) -> bool {
+        self.initialized && self.name.map(CStr::to_bytes) == Some(name.as_bytes())
+    }
+}
+
+/// An iterator over the up or down channels in the control block.
+///
+/// Created with [`up_channels`] or [`down_channels`].
+pub struct Channels {
+    channel: fn(usize) -> Option<*mut RttChannel>,
+    number: usize,
+}
+
+impl Iterator for Channels {
+    type Item = ChannelInfo;
+
+    fn next(&mut self) -> Option<ChannelInfo> {
+        let ptr = (self.channel)(self.number)?;
+        let number = self.number;
+        self.number += 1;
+
+        // Safety: the pointer points to a channel within the control block.
+        let chan = unsafe { &*ptr };
+
+        Some(ChannelInfo {
+            number,
+            name: chan.name(),
+            size: chan.size(),
+            mode: chan.mode(),
+            initialized: chan.is_initialized(),
+        })
+    }
+}
+
+/// Returns an iterator over all up channels in the control block, including the uninitialized
+/// ones. The iterator is empty if RTT has not been initialized yet.
+///
+/// Calling this function will cause a linking error if `rtt_init` has not been called.
+///
+/// ```
+/// for info in up_channels() {
+///     rprintln!("{}: {:?} ({} bytes)", info.number, info.name, info.size);
+/// }
+/// ```
+pub fn up_channels() -> Channels {
+    Channels {
+        channel: rtt::up_channel,
+        number: 0,
+    }
+}
+
+/// Returns an iterator over all down channels in the control block. See [`up_channels`].
+pub fn down_channels() -> Channels {
+    Channels {
+        channel: rtt::down_channel,
+        number: 0,
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{DownChannel, TerminalChannel, UpChannel};
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+/// Output used by [`LineEditor`] to echo edits back to the host.
+///
+/// Implemented for [`UpChannel`], for [`TerminalChannel`] (echoing to virtual terminal 0) and for
+/// closures taking the bytes to echo. The latter can be used to echo through the print channel:
+///
+/// ```
+/// let mut echo = |bytes: &[u8]| with_terminal_channel(|term| term.echo(bytes));
+/// ```
+pub trait Echo {
+    /// Writes `bytes` to the host.
+    fn echo(&mut self, bytes: &[u8]);
+}
+
+impl Echo for UpChannel {
+    fn echo(&mut self, bytes: &[u8]) {
+        self.write(bytes);
+    }
+}
+
+impl Echo for TerminalChannel {
+    fn echo(&mut self, bytes: &[u8]) {
+        self.write(0).write_bytes(bytes);
+    }
+}
+
+impl<F: FnMut(&[u8])> Echo for F {
+    fn echo(&mut self, bytes: &[u8]) {
+        self(bytes)
+    }
+}
+
+/// An event produced by [`LineEditor`].
+pub enum LineEvent<'a> {
+    /// A line was completed with Enter. The line does not include the line terminator.
+    Line(&'a [u8]),
+
+    /// The line was cancelled with Ctrl-C.
+    Interrupt,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum EscapeState {
+    None,
+    Escape,
+    Csi,
+}
+
+/// A line editor for interactive consoles on a down channel, conventionally down channel 0.
+///
+/// The editor reads keystrokes from a [`DownChannel`] and echoes the edits back through an
+/// [`Echo`] output, so that a terminal connected to the RTT channels behaves like a serial console.
+/// Lines are at most `LEN` bytes long, and the last `HISTORY` lines are kept for recalling with the
+/// up and down arrow keys.
+///
+/// Supported keys are Backspace, Enter, Ctrl-C (cancel the line), Ctrl-U (clear the line) and the
+/// up and down arrows. Other control characters and escape sequences are ignored.
+///
+/// Note that some host programs send input a line at a time and do their own echoing, in which
+/// case the editing keys are handled by the host instead.
+///
+/// ```
+/// let mut input = channels.down.0;
+/// let mut output = channels.up.0;
+/// let mut editor = LineEditor::<64, 4>::new();
+///
+/// loop {
+///     match editor.read_line(&mut input, &mut output) {
+///         LineEvent::Line(line) => handle_command(line),
+///         LineEvent::Interrupt => {}
+///     }
+/// }
+/// ```
+pub struct LineEditor<const LEN: usize, const HISTORY: usize> {
+    line: [u8; LEN],
+    len: usize,
+    done: bool,
+    skip_lf: bool,
+    escape: EscapeState,
+    history: [[u8; LEN]; HISTORY],
+    history_lens: [usize; HISTORY],
+    history_count: usize,
+    history_next: usize,
+    browsing: Option<usize>,
+}
+
+impl<const LEN: usize, const HISTORY: usize> LineEditor<LEN, HISTORY> {
+    /// Creates a new line editor with an empty line and history.
+    pub const fn new() -> Self {
+        LineEditor {
+            line: [0; LEN],
+            len: 0,
+            done: false,
+            skip_lf: false,
+            escape: EscapeState::None,
+            history: [[0; LEN]; HISTORY],
+            history_lens: [0; HISTORY],
+            history_count: 0,
+            history_next: 0,
+            browsing: None,
+        }
+    }
+
+    /// Processes the input available on `input` without blocking, echoing edits to `output`.
+    /// Returns an event if a line was completed or cancelled, in which case any further input is
+    /// left in the channel for the next call.
+    pub fn poll(
+        &mut self,
+        input: &mut DownChannel,
+        output: &mut impl Echo,
+    ) -> Option<LineEvent<'_>> {
+        if self.done {
+            self.done = false;
+            self.len = 0;
+        }
+
+        while let Some(grant) = input.read_grant() {
+            let mut used = 0;
+            let mut event = None;
+
+            for &byte in grant.buf() {
+                used += 1;
+
+                event = self.process(byte, output);
+                if event.is_some() {
+                    break;
+                }
+            }
+
+            grant.release(used);
+
+            match event {
+                Some(Event::Line) => {
+                    self.done = true;
+                    return Some(LineEvent::Line(&self.line[..self.len]));
+                }
+                Some(Event::Interrupt) => return Some(LineEvent::Interrupt),
+                None => (),
+            }
+        }
+
+        None
+    }
+
+    /// Reads a line from `input`, blocking until a line is completed or cancelled. Edits are
+    /// echoed to `output`.
+    pub fn read_line(&mut self, input: &mut DownChannel, output: &mut impl Echo) -> LineEvent<'_> {
+        loop {
+            // The borrowed event cannot be returned from within the loop due to a borrow checker
+            // limitation, so it is recreated below.
+            if self.poll(input, output).is_some() {
+                break;
+            }
+
+            core::hint::spin_loop();
+        }
+
+        if self.done {
+            LineEvent::Line(&self.line[..self.len])
+        } else {
+            LineEvent::Interrupt
+        }
+    }
+
+    fn process(&mut self, byte: u8, output: &mut impl Echo) -> Option<Event> {
+        let skip_lf = core::mem::replace(&mut self.skip_lf, false);
+
+        match self.escape {
+            EscapeState::Escape => {
+                // A lone escape is ignored and the byte following it is processed normally
+                if byte == b'[' {
+                    self.escape = EscapeState::Csi;
+                    return None;
+                }
+
+                self.escape = EscapeState::None;
+            }
+            EscapeState::Csi => {
+                // Parameter bytes are skipped until the final byte of the sequence
+                if (0x40..=0x7e).contains(&byte) {
+                    self.escape = EscapeState::None;
+
+                    match byte {
+                        b'A' => self.history_previous(output),
+                        b'B' => self.history_next(output),
+                        _ => (),
+                    }
+                }
+                return None;
+            }
+            EscapeState::None => (),
+        }
+
+        match byte {
+            b'\n' if skip_lf => (),
+            b'\r' | b'\n' => {
+                self.skip_lf = byte == b'\r';
+                output.echo(b"\r\n");
+                self.push_history();
+                self.browsing = None;
+                return Some(Event::Line);
+            }
+            CTRL_C => {
+                output.echo(b"^C\r\n");
+                self.len = 0;
+                self.browsing = None;
+                return Some(Event::Interrupt);
+            }
+            CTRL_U => self.clear(output),
+            BACKSPACE | DEL => {
+                if self.len > 0 {
+                    // Remove a whole UTF-8 encoded character
+                    self.len -= 1;
+                    while self.len > 0 && self.line[self.len] & 0xc0 == 0x80 {
+                        self.len -= 1;
+                    }
+
+                    output.echo(b"\x08 \x08");
+                }
+            }
+            ESC => self.escape = EscapeState::Escape,
+            byte if byte < 0x20 => (),
+            byte => {
+                if self.len < LEN {
+                    self.line[self.len] = byte;
+                    self.len += 1;
+                    output.echo(&[byte]);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Erases the current line on the terminal and empties it.
+    fn clear(&mut self, output: &mut impl Echo) {
+        let chars = self.line[..self.len]
+            .iter()
+            .filter(|&&b| b & 0xc0 != 0x80)
+            .count();
+
+        for _ in 0..chars {
+            output.echo(b"\x08 \x08");
+        }
+
+        self.len = 0;
+    }
+
+    fn push_history(&mut self) {
+        if HISTORY == 0 || self.len == 0 {
+            return;
+        }
+
+        // Don't store repeated lines
+        if self.history_count > 0 {
+            let last = (self.history_next + HISTORY - 1) % HISTORY;
+            if self.history[last][..self.history_lens[last]] == self.line[..self.len] {
+                return;
+            }
+        }
+
+        self.history[self.history_next] = self.line;
+        self.history_lens[self.history_next] = self.len;
+        self.history_next = (self.history_next + 1) % HISTORY;
+        self.history_count = (self.history_count + 1).min(HISTORY);
+    }
+
+    fn history_previous(&mut self, output: &mut impl Echo) {
+        let age = self.browsing.map_or(0, |age| age + 1);
+        if age < self.history_count {
+            self.recall(age, output);
+        }
+    }
+
+    fn history_next(&mut self, output: &mut impl Echo) {
+        match self.browsing {
+            Some(0) => {
+                self.clear(output);
+                self.browsing = None;
+            }
+            Some(age) => self.recall(age - 1, output),
+            None => (),
+        }
+    }
+
+    /// Replaces the current line with the history entry `age` lines back.
+    fn recall(&mut self, age: usize, output: &mut impl Echo) {
+        let index = (self.history_next + HISTORY - 1 - age) % HISTORY;
+
+        self.clear(output);
+        self.len = self.history_lens[index];
+        self.line = self.history[index];
+        self.browsing = Some(age);
+
+        output.echo(&self.line[..self.len]);
+    }
+}
+
+impl<const LEN: usize, const HISTORY: usize> Default for LineEditor<LEN, HISTORY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum Event {
+    Line,
+    Interrupt,
+}
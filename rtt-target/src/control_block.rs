@@ -0,0 +1,189 @@
+use crate::buffer;
+use crate::rtt::{RttChannel, RttHeader};
+use crate::{ChannelBuffer, ChannelMode, DownChannel, UpChannel};
+use core::cell::{Cell, UnsafeCell};
+use core::ffi::CStr;
+use core::ptr;
+use critical_section::Mutex;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNINIT_CHANNEL: RttChannel = RttChannel::new();
+
+#[repr(C)]
+struct RawControlBlock<const UP: usize, const DOWN: usize> {
+    header: RttHeader,
+    up_channels: [RttChannel; UP],
+    down_channels: [RttChannel; DOWN],
+}
+
+/// An RTT control block with room for `UP` up channels and `DOWN` down channels, as an alternative
+/// to the [`rtt_init`](crate::rtt_init) macro.
+///
+/// The control block is placed in a `static` that must be exported as `_SEGGER_RTT`, so that the
+/// host and [`UpChannel::conjure`] can find it. It is then initialized at runtime with
+/// [`init`](ControlBlock::init), which takes a [`ChannelConfig`] for each channel and returns the
+/// channel objects:
+///
+/// ```
+/// #[export_name = "_SEGGER_RTT"]
+/// static RTT: ControlBlock<2, 1> = ControlBlock::new();
+///
+/// let terminal = cortex_m::singleton!(: [u8; 1024] = [0; 1024]).unwrap();
+/// let data = cortex_m::singleton!(: [u8; 256] = [0; 256]).unwrap();
+/// let input = cortex_m::singleton!(: [u8; 16] = [0; 16]).unwrap();
+///
+/// let ([terminal, data], [input]) = RTT.init(
+///     [
+///         ChannelConfig::new(terminal).name(c"Terminal"),
+///         ChannelConfig::new(data).mode(ChannelMode::BlockIfFull),
+///     ],
+///     [ChannelConfig::new(input).name(c"Terminal")],
+/// );
+/// ```
+///
+/// The memory layout is the same as the one created by `rtt_init`, so only one of them can be used
+/// in a program.
+#[repr(C)]
+pub struct ControlBlock<const UP: usize, const DOWN: usize> {
+    cb: UnsafeCell<RawControlBlock<UP, DOWN>>,
+    initialized: Mutex<Cell<bool>>,
+}
+
+unsafe impl<const UP: usize, const DOWN: usize> Sync for ControlBlock<UP, DOWN> {}
+
+impl<const UP: usize, const DOWN: usize> ControlBlock<UP, DOWN> {
+    /// Creates an uninitialized control block. The host won't detect the control block until it
+    /// has been initialized.
+    pub const fn new() -> Self {
+        ControlBlock {
+            cb: UnsafeCell::new(RawControlBlock {
+                header: RttHeader::new(),
+                up_channels: [UNINIT_CHANNEL; UP],
+                down_channels: [UNINIT_CHANNEL; DOWN],
+            }),
+            initialized: Mutex::new(Cell::new(false)),
+        }
+    }
+
+    /// Initializes the control block with the given up and down channels, and returns the channel
+    /// objects in the same order.
+    ///
+    /// There can be fewer channels than the control block has room for, in which case the rest are
+    /// left uninitialized. Like the channels without a size in [`rtt_init`](crate::rtt_init), they
+    /// can be initialized later by other code.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more channels than the control block has room for, or if the control
+    /// block has already been initialized.
+    pub fn init<const N: usize, const M: usize>(
+        &'static self,
+        up: [ChannelConfig; N],
+        down: [ChannelConfig; M],
+    ) -> ([UpChannel; N], [DownChannel; M]) {
+        assert!(N <= UP && M <= DOWN, "too many RTT channels");
+
+        critical_section::with(|cs| {
+            if self.initialized.borrow(cs).replace(true) {
+                panic!("ControlBlock::init must not be called multiple times");
+            }
+        });
+
+        let cb = self.cb.get();
+
+        unsafe {
+            let up_channels = ptr::addr_of_mut!((*cb).up_channels).cast::<RttChannel>();
+            let down_channels = ptr::addr_of_mut!((*cb).down_channels).cast::<RttChannel>();
+
+            for (i, config) in IntoIterator::into_iter(up).enumerate() {
+                config.apply(&mut *up_channels.add(i));
+            }
+
+            for (i, config) in IntoIterator::into_iter(down).enumerate() {
+                config.apply(&mut *down_channels.add(i));
+            }
+
+            // The header is initialized last to make it less likely an unfinished control block is
+            // detected by the host.
+
+            (*cb).header.init(UP, DOWN);
+
+            (
+                core::array::from_fn(|i| UpChannel::new(up_channels.add(i))),
+                core::array::from_fn(|i| DownChannel::new(down_channels.add(i))),
+            )
+        }
+    }
+}
+
+impl<const UP: usize, const DOWN: usize> Default for ControlBlock<UP, DOWN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Initializes the first uninitialized channel returned by `channel` according to `config`.
+pub(crate) fn claim(
+    channel: fn(usize) -> Option<*mut RttChannel>,
+    config: ChannelConfig,
+) -> Option<*mut RttChannel> {
+    // The critical section prevents two claims from initializing the same channel
+    critical_section::with(|_| {
+        let mut number = 0;
+
+        while let Some(ptr) = channel(number) {
+            unsafe {
+                if !(*ptr).is_initialized() {
+                    config.apply(&mut *ptr);
+                    return Some(ptr);
+                }
+            }
+
+            number += 1;
+        }
+
+        None
+    })
+}
+
+/// The configuration of a channel in a [`ControlBlock`], or of a channel claimed with
+/// [`UpChannel::claim`] or [`DownChannel::claim`].
+pub struct ChannelConfig {
+    buffer: *mut [u8],
+    name: Option<&'static CStr>,
+    mode: ChannelMode,
+}
+
+impl ChannelConfig {
+    /// Creates a configuration for a channel using `buffer`, without a name and in `NoBlockSkip`
+    /// mode.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer is shorter than 2 bytes.
+    pub fn new(buffer: impl ChannelBuffer) -> Self {
+        ChannelConfig {
+            buffer: buffer::into_raw(buffer),
+            name: None,
+            mode: ChannelMode::NoBlockSkip,
+        }
+    }
+
+    /// Sets the name of the channel.
+    pub fn name(mut self, name: &'static CStr) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Sets the mode of the channel. The mode is only used for up channels.
+    pub fn mode(mut self, mode: ChannelMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub(crate) unsafe fn apply(self, channel: &mut RttChannel) {
+        let name = self.name.map_or(ptr::null(), |name| name.as_ptr().cast());
+
+        channel.init(name, self.mode, self.buffer);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::rtt::RttChannel;
+use crate::{DownChannel, UpChannel};
+use core::marker::PhantomData;
+use core::ptr;
+use linkme::distributed_slice;
+use portable_atomic::{AtomicPtr, Ordering::SeqCst};
+
+/// Initializers for the up channels declared with [`declare_up_channel`](crate::declare_up_channel).
+#[doc(hidden)]
+#[distributed_slice]
+pub static DECLARED_UP_CHANNELS: [fn(&mut RttChannel)];
+
+/// Initializers for the down channels declared with
+/// [`declare_down_channel`](crate::declare_down_channel).
+#[doc(hidden)]
+#[distributed_slice]
+pub static DECLARED_DOWN_CHANNELS: [fn(&mut RttChannel)];
+
+pub(crate) fn init(up_channels: &mut [RttChannel], down_channels: &mut [RttChannel]) {
+    assert!(
+        DECLARED_UP_CHANNELS.len() <= up_channels.len(),
+        "rtt_init! reserves fewer up channels with declared_up than are declared"
+    );
+    assert!(
+        DECLARED_DOWN_CHANNELS.len() <= down_channels.len(),
+        "rtt_init! reserves fewer down channels with declared_down than are declared"
+    );
+
+    // Reserved channels that no library declared are reset, as they may still be initialized in a
+    // retained control block.
+    for (i, channel) in up_channels.iter_mut().enumerate() {
+        match DECLARED_UP_CHANNELS.get(i) {
+            Some(init) => init(channel),
+            None => channel.reset(),
+        }
+    }
+
+    for (i, channel) in down_channels.iter_mut().enumerate() {
+        match DECLARED_DOWN_CHANNELS.get(i) {
+            Some(init) => init(channel),
+            None => channel.reset(),
+        }
+    }
+}
+
+/// A channel declared by a library with [`declare_up_channel`](crate::declare_up_channel) or
+/// [`declare_down_channel`](crate::declare_down_channel).
+///
+/// The channel is allocated and initialized by [`rtt_init`](crate::rtt_init) in the application,
+/// and can then be taken by the library with [`take`](DeclaredChannel::take).
+pub struct DeclaredChannel<T> {
+    channel: AtomicPtr<RttChannel>,
+    _type: PhantomData<T>,
+}
+
+unsafe impl<T> Sync for DeclaredChannel<T> {}
+
+impl<T> DeclaredChannel<T> {
+    #[doc(hidden)]
+    pub const fn new() -> Self {
+        DeclaredChannel {
+            channel: AtomicPtr::new(ptr::null_mut()),
+            _type: PhantomData,
+        }
+    }
+
+    /// Sets the channel to be taken with [`take`](DeclaredChannel::take).
+    ///
+    /// # Safety
+    ///
+    /// `channel` must point to an initialized channel of the right direction in the control block,
+    /// which is not used through any other handle.
+    #[doc(hidden)]
+    pub unsafe fn set(&self, channel: *mut RttChannel) {
+        self.channel.store(channel, SeqCst);
+    }
+
+    fn take_ptr(&self) -> Option<*mut RttChannel> {
+        critical_section::with(|_| {
+            let channel = self.channel.load(SeqCst);
+            self.channel.store(ptr::null_mut(), SeqCst);
+
+            if channel.is_null() {
+                None
+            } else {
+                Some(channel)
+            }
+        })
+    }
+}
+
+impl DeclaredChannel<UpChannel> {
+    /// Takes the channel. Returns `None` if the channel has already been taken, or if RTT has not
+    /// been initialized yet.
+    pub fn take(&self) -> Option<UpChannel> {
+        self.take_ptr().map(|ptr| unsafe { UpChannel::new(ptr) })
+    }
+}
+
+impl DeclaredChannel<DownChannel> {
+    /// Takes the channel. Returns `None` if the channel has already been taken, or if RTT has not
+    /// been initialized yet.
+    pub fn take(&self) -> Option<DownChannel> {
+        self.take_ptr().map(|ptr| unsafe { DownChannel::new(ptr) })
+    }
+}
+
+/// Declares an up channel for a library, without the application having to list it in
+/// [`rtt_init`](crate::rtt_init).
+///
+/// The declared channels are collected at link time, and `rtt_init` lays them out in the channels
+/// it reserves with its `declared_up` parameter. `rtt_init` panics if it reserves fewer channels
+/// than are declared. The channel can then be taken through the
+/// declared static with [`DeclaredChannel::take`]. The parameters are the same as in `rtt_init`.
+///
+/// This requires the `declared-channels` feature, which uses the [`linkme`] crate. See its
+/// documentation for the supported platforms.
+///
+/// ```
+/// // In the library
+/// rtt_target::declare_up_channel! {
+///     pub static TRACE: { size: 1024, name: "Trace" }
+/// }
+///
+/// let mut output = TRACE.take().unwrap();
+///
+/// // In the application
+/// let channels = rtt_init! {
+///     up: {
+///         0: { size: 1024 name: "Terminal" }
+///     }
+///     declared_up: 1
+/// };
+/// ```
+///
+/// [`linkme`]: https://docs.rs/linkme
+#[macro_export]
+#[cfg(feature = "declared-channels")]
+macro_rules! declare_up_channel {
+    {
+        $(#[$attr:meta])*
+        $vis:vis static $ident:ident: {
+            size: $size:expr
+            $(, mode: $mode:path )?
+            $(, name: $name:literal )?
+            $(, section: $section:literal )?
+            $(,)?
+        }
+    } => {
+        $crate::declare_channel!(
+            $(#[$attr])* $vis $ident; UpChannel; DECLARED_UP_CHANNELS; init_retained;
+            $size; $($mode)?; $($name)?; $($section)?
+        );
+    };
+}
+
+/// Declares a down channel for a library. See [`declare_up_channel`](crate::declare_up_channel)
+/// for details. The channels are laid out in the channels reserved with the `declared_down`
+/// parameter of `rtt_init`.
+#[macro_export]
+#[cfg(feature = "declared-channels")]
+macro_rules! declare_down_channel {
+    {
+        $(#[$attr:meta])*
+        $vis:vis static $ident:ident: {
+            size: $size:expr
+            $(, mode: $mode:path )?
+            $(, name: $name:literal )?
+            $(, section: $section:literal )?
+            $(,)?
+        }
+    } => {
+        $crate::declare_channel!(
+            $(#[$attr])* $vis $ident; DownChannel; DECLARED_DOWN_CHANNELS; init;
+            $size; $($mode)?; $($name)?; $($section)?
+        );
+    };
+}
+
+/// declare_up_channel! implementation detail
+#[macro_export]
+#[doc(hidden)]
+#[cfg(feature = "declared-channels")]
+macro_rules! declare_channel {
+    (
+        $(#[$attr:meta])* $vis:vis $ident:ident; $ty:ident; $slice:ident; $init:ident;
+        $size:expr; $($mode:path)?; $($name:literal)?; $($section:literal)?
+    ) => {
+        $(#[$attr])*
+        $vis static $ident: $crate::DeclaredChannel<$crate::$ty> = $crate::DeclaredChannel::new();
+
+        const _: () = {
+            use $crate::export::linkme;
+
+            #[linkme::distributed_slice($crate::rtt::$slice)]
+            #[linkme(crate = $crate::export::linkme)]
+            static DECLARATION: fn(&mut $crate::rtt::RttChannel) = |channel| unsafe {
+                let mut name: *const u8 = core::ptr::null();
+                $( name = concat!($name, "\0").as_bytes().as_ptr(); )?
+
+                let mut mode = $crate::ChannelMode::NoBlockSkip;
+                $( mode = $mode; )?
+
+                $( #[link_section = $section] )?
+                static mut BUFFER: core::mem::MaybeUninit<[u8; $size]> =
+                    core::mem::MaybeUninit::uninit();
+
+                channel.$init(name, mode, core::ptr::addr_of_mut!(BUFFER).cast::<[u8; $size]>());
+                $ident.set(channel);
+            };
+        };
+    };
+}
@@ -0,0 +1,95 @@
+use crate::{ChannelMode, DownChannel, UpChannel};
+use core::cmp::min;
+use core::convert::Infallible;
+use embedded_io::{ErrorType, Read, ReadReady, Write, WriteReady};
+
+impl ErrorType for UpChannel {
+    type Error = Infallible;
+}
+
+impl Write for UpChannel {
+    /// Writes `buf` to the channel according to the channel mode.
+    ///
+    /// In the non-blocking modes this never blocks, and data that does not fit is discarded as
+    /// specified by the mode. The discarded data still counts as written, so the full length of
+    /// `buf` is always returned. The same applies to `BlockWithTimeout` and `Adaptive` mode once
+    /// the timeout expires.
+    ///
+    /// In `BlockIfFull` mode this blocks until at least one byte fits, and then writes as much as
+    /// fits without blocking again.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.mode() != ChannelMode::BlockIfFull {
+            UpChannel::write(self, buf);
+            return Ok(buf.len());
+        }
+
+        while self.channel().free() == 0 {
+            core::hint::spin_loop();
+        }
+
+        Ok(self.write_available(buf))
+    }
+
+    /// Waits until all data has been read by the debugger. See [`UpChannel::flush`].
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        UpChannel::flush(self);
+        Ok(())
+    }
+}
+
+impl UpChannel {
+    /// Writes as much of `buf` as fits without blocking. Only the data written counts towards the
+    /// channel statistics, as the caller is told how much was written.
+    pub(crate) fn write_available(&mut self, buf: &[u8]) -> usize {
+        let mut writer = self.writer();
+
+        let (contiguous, wrapped) = writer.writable_regions();
+        let len = min(buf.len(), contiguous + wrapped);
+
+        writer.write_with_mode(ChannelMode::NoBlockTrim, &buf[..len]);
+        writer.commit()
+    }
+}
+
+impl WriteReady for UpChannel {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(match self.mode() {
+            ChannelMode::BlockIfFull | ChannelMode::BlockWithTimeout | ChannelMode::Adaptive => {
+                self.channel().free() > 0
+            }
+            _ => true,
+        })
+    }
+}
+
+impl ErrorType for DownChannel {
+    type Error = Infallible;
+}
+
+impl Read for DownChannel {
+    /// Reads up to `buf.len()` bytes from the channel, blocking until at least one byte is
+    /// available.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let count = DownChannel::read(self, buf);
+            if count > 0 {
+                return Ok(count);
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl ReadReady for DownChannel {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.channel().available() > 0)
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{DownChannel, UpChannel};
+use core::cell::Cell;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+use critical_section::Mutex;
+use embedded_io_async::{Read, Write};
+
+/// Specifies how the async channel implementations wait for the host.
+///
+/// The host does not notify the target when it reads or writes a buffer, so a pending read or
+/// write has to check the channel again later. The strategy decides when that happens.
+#[derive(Clone, Copy)]
+pub enum PollStrategy {
+    /// Wake the task immediately so that it is polled again as soon as the executor gets to it.
+    /// Other tasks still get to run in between, but the executor will not go to sleep while a task
+    /// is waiting for a channel.
+    Yield,
+
+    /// Call a function with the waker of the waiting task. The function must arrange for the waker
+    /// to be woken at a later point, for example from a timer interrupt, so that the channel is
+    /// checked again.
+    Custom(fn(&Waker)),
+}
+
+static POLL_STRATEGY: Mutex<Cell<PollStrategy>> = Mutex::new(Cell::new(PollStrategy::Yield));
+
+/// Sets the strategy the async channel implementations use to wait for the host. The default is
+/// [`PollStrategy::Yield`].
+pub fn set_poll_strategy(strategy: PollStrategy) {
+    critical_section::with(|cs| POLL_STRATEGY.borrow(cs).set(strategy));
+}
+
+/// Waits until `ready` returns true, checking again according to the poll strategy.
+async fn wait_until(mut ready: impl FnMut() -> bool) {
+    poll_fn(|cx| {
+        if ready() {
+            return Poll::Ready(());
+        }
+
+        match critical_section::with(|cs| POLL_STRATEGY.borrow(cs).get()) {
+            PollStrategy::Yield => cx.waker().wake_by_ref(),
+            PollStrategy::Custom(schedule) => schedule(cx.waker()),
+        }
+
+        Poll::Pending
+    })
+    .await
+}
+
+impl Write for UpChannel {
+    /// Writes `buf` to the channel, waiting until at least one byte fits. As much as fits is then
+    /// written and the number of bytes written is returned.
+    ///
+    /// Unlike the blocking implementations this waits regardless of the channel mode, as waiting
+    /// does not block the executor.
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        wait_until(|| self.channel().free() > 0).await;
+
+        Ok(self.write_available(buf))
+    }
+
+    /// Waits until all data has been read by the debugger.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        wait_until(|| self.is_empty()).await;
+        Ok(())
+    }
+}
+
+impl Read for DownChannel {
+    /// Reads up to `buf.len()` bytes from the channel, waiting until at least one byte is
+    /// available.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        wait_until(|| self.channel().available() > 0).await;
+
+        Ok(DownChannel::read(self, buf))
+    }
+}
@@ -0,0 +1,186 @@
+use crate::rtt::{RttChannel, RttWriter};
+use core::cmp::min;
+use core::ops::{Deref, DerefMut};
+
+/// A contiguous region of an up channel buffer reserved for writing.
+///
+/// Created with [`UpChannel::grant_exact`](crate::UpChannel::grant_exact) or
+/// [`UpChannel::grant_max_remaining`](crate::UpChannel::grant_max_remaining). Data written into the
+/// grant becomes visible to the host only once it is committed with [`commit`](WriteGrant::commit).
+/// Dropping the grant without committing it discards the data.
+pub struct WriteGrant<'c> {
+    writer: RttWriter<'c>,
+    buf: &'c mut [u8],
+}
+
+impl<'c> WriteGrant<'c> {
+    pub(crate) fn new(writer: RttWriter<'c>, len: usize) -> Self {
+        // Safety: the caller has checked that `len` bytes following the write position are free.
+        let buf = unsafe { writer.buffer_at_write(len) };
+
+        WriteGrant { writer, buf }
+    }
+
+    /// Gets the reserved region of the buffer.
+    pub fn buf(&mut self) -> &mut [u8] {
+        self.buf
+    }
+
+    /// Makes the first `used` bytes of the grant visible to the host. The rest of the reserved
+    /// region is released without being written.
+    pub fn commit(mut self, used: usize) {
+        self.writer.advance(min(used, self.buf.len()));
+        self.writer.commit();
+    }
+
+    /// Releases the grant without writing anything to the channel.
+    pub fn abort(mut self) {
+        self.writer.abort();
+    }
+}
+
+impl Deref for WriteGrant<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buf
+    }
+}
+
+impl DerefMut for WriteGrant<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buf
+    }
+}
+
+/// A region of an up channel buffer reserved for writing that may wrap around the end of the
+/// buffer.
+///
+/// Created with [`UpChannel::split_grant`](crate::UpChannel::split_grant). The region is made up of
+/// two slices: the contiguous space at the write position, followed by the space at the start of
+/// the buffer. The second slice is empty if the region does not wrap around. Data is committed in
+/// order, so the first slice is filled before the second one.
+pub struct SplitWriteGrant<'c> {
+    writer: RttWriter<'c>,
+    buf1: &'c mut [u8],
+    buf2: &'c mut [u8],
+}
+
+impl<'c> SplitWriteGrant<'c> {
+    pub(crate) fn new(writer: RttWriter<'c>, len1: usize, len2: usize) -> Self {
+        // Safety: the caller has checked that `len1` bytes following the write position and `len2`
+        // bytes at the start of the buffer are free. The regions do not overlap, because the free
+        // space never includes the byte before the read pointer.
+        let (buf1, buf2) = unsafe { (writer.buffer_at_write(len1), writer.buffer_at_start(len2)) };
+
+        SplitWriteGrant { writer, buf1, buf2 }
+    }
+
+    /// Gets the two slices that make up the reserved region.
+    pub fn bufs_mut(&mut self) -> (&mut [u8], &mut [u8]) {
+        (self.buf1, self.buf2)
+    }
+
+    /// Gets the total length of the reserved region.
+    pub fn len(&self) -> usize {
+        self.buf1.len() + self.buf2.len()
+    }
+
+    /// Returns true if the reserved region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Makes the first `used` bytes of the grant visible to the host, counting from the start of
+    /// the first slice. The rest of the reserved region is released without being written.
+    pub fn commit(mut self, used: usize) {
+        self.writer.advance(min(used, self.len()));
+        self.writer.commit();
+    }
+
+    /// Releases the grant without writing anything to the channel.
+    pub fn abort(mut self) {
+        self.writer.abort();
+    }
+}
+
+/// A contiguous region of a down channel buffer borrowed for reading.
+///
+/// Created with [`DownChannel::read_grant`](crate::DownChannel::read_grant). The data stays in the
+/// buffer until it is released with [`release`](ReadGrant::release), so a partially received frame
+/// can be left in place until the rest of it arrives. Dropping the grant releases nothing.
+pub struct ReadGrant<'c> {
+    chan: &'c RttChannel,
+    buf: &'c [u8],
+}
+
+impl<'c> ReadGrant<'c> {
+    pub(crate) fn new(chan: &'c RttChannel, len: usize) -> Self {
+        // Safety: the caller has checked that `len` bytes following the read pointer are readable.
+        let buf = unsafe { chan.buffer_at_read(len) };
+
+        ReadGrant { chan, buf }
+    }
+
+    /// Gets the borrowed region of the buffer.
+    pub fn buf(&self) -> &[u8] {
+        self.buf
+    }
+
+    /// Releases the first `used` bytes of the grant, making the space available to the host again.
+    /// The rest of the data will be returned again by the next read.
+    pub fn release(self, used: usize) {
+        self.chan.consume(min(used, self.buf.len()));
+    }
+}
+
+impl Deref for ReadGrant<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buf
+    }
+}
+
+/// A region of a down channel buffer borrowed for reading that may wrap around the end of the
+/// buffer.
+///
+/// Created with [`DownChannel::split_read_grant`](crate::DownChannel::split_read_grant). The region
+/// is made up of two slices: the contiguous data at the read pointer, followed by the data at the
+/// start of the buffer. The second slice is empty if the data does not wrap around.
+pub struct SplitReadGrant<'c> {
+    chan: &'c RttChannel,
+    buf1: &'c [u8],
+    buf2: &'c [u8],
+}
+
+impl<'c> SplitReadGrant<'c> {
+    pub(crate) fn new(chan: &'c RttChannel, len1: usize, len2: usize) -> Self {
+        // Safety: the caller has checked that `len1` bytes following the read pointer and `len2`
+        // bytes at the start of the buffer are readable.
+        let (buf1, buf2) = unsafe { (chan.buffer_at_read(len1), chan.buffer_at_start(len2)) };
+
+        SplitReadGrant { chan, buf1, buf2 }
+    }
+
+    /// Gets the two slices that make up the borrowed region.
+    pub fn bufs(&self) -> (&[u8], &[u8]) {
+        (self.buf1, self.buf2)
+    }
+
+    /// Gets the total length of the borrowed region.
+    pub fn len(&self) -> usize {
+        self.buf1.len() + self.buf2.len()
+    }
+
+    /// Returns true if the borrowed region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Releases the first `used` bytes of the grant, counting from the start of the first slice.
+    /// The rest of the data will be returned again by the next read.
+    pub fn release(self, used: usize) {
+        self.chan.consume(min(used, self.len()));
+    }
+}
@@ -15,35 +15,65 @@ macro_rules! rtt_init_repeat {
 #[doc(hidden)]
 macro_rules! rtt_init_channels {
     (
-        $field:expr;
+        $field:expr; $init:ident;
         $number:literal: {
-            $(
-                size: $size:expr
-                $(, mode: $mode:path )?
-                $(, name: $name:literal )?
-                $(, section: $section:literal )?
-                $(,)?
-            )?
+            buffer: $buffer:expr
+            $(, mode: $mode:path )?
+            $(, name: $name:literal )?
+            $(,)?
         }
         $($tail:tt)*
     ) => {
-        $(
-            let mut name: *const u8 = core::ptr::null();
-            $( name = concat!($name, "\0").as_bytes().as_ptr(); )?
+        let mut name: *const u8 = core::ptr::null();
+        $( name = concat!($name, "\0").as_bytes().as_ptr(); )?
 
-            let mut mode = $crate::ChannelMode::NoBlockSkip;
-            $( mode = $mode; )?
+        let mut mode = $crate::ChannelMode::NoBlockSkip;
+        $( mode = $mode; )?
 
-            $field[$number].init(name, mode, {
-                $( #[link_section = $section] )?
-                static mut _RTT_CHANNEL_BUFFER: MaybeUninit<[u8; $size]> = MaybeUninit::uninit();
-                _RTT_CHANNEL_BUFFER.as_mut_ptr()
-            });
-        )?
+        // The buffer expression is evaluated outside of the unsafe block, so that it can't use
+        // unsafe code without an unsafe block of its own.
+        let buffer = $crate::rtt::channel_buffer($buffer);
+        unsafe { $field[$number].$init(name, mode, buffer) };
 
-        $crate::rtt_init_channels!($field; $($tail)*);
+        $crate::rtt_init_channels!($field; $init; $($tail)*);
     };
-    ($field:expr;) => { };
+    (
+        $field:expr; $init:ident;
+        $number:literal: {
+            size: $size:expr
+            $(, mode: $mode:path )?
+            $(, name: $name:literal )?
+            $(, section: $section:literal )?
+            $(,)?
+        }
+        $($tail:tt)*
+    ) => {
+        let mut name: *const u8 = core::ptr::null();
+        $( name = concat!($name, "\0").as_bytes().as_ptr(); )?
+
+        let mut mode = $crate::ChannelMode::NoBlockSkip;
+        $( mode = $mode; )?
+
+        let buffer: *mut [u8] = {
+            $( #[link_section = $section] )?
+            static mut _RTT_CHANNEL_BUFFER: MaybeUninit<[u8; $size]> = MaybeUninit::uninit();
+            unsafe { _RTT_CHANNEL_BUFFER.as_mut_ptr() }
+        };
+        unsafe { $field[$number].$init(name, mode, buffer) };
+
+        $crate::rtt_init_channels!($field; $init; $($tail)*);
+    };
+    (
+        $field:expr; $init:ident;
+        $number:literal: { }
+        $($tail:tt)*
+    ) => {
+        // A pre-allocated channel may still be initialized in a retained control block
+        $field[$number].reset();
+
+        $crate::rtt_init_channels!($field; $init; $($tail)*);
+    };
+    ($field:expr; $init:ident;) => { };
 }
 
 /// rtt_init! implementation detail
@@ -86,6 +116,10 @@ macro_rules! rtt_init_wrappers {
 ///             size: 32
 ///         }
 ///         2: { } // pre-allocated channel without buffer
+///         3: {
+///             buffer: dma_buffer, // existing buffer (instead of size)
+///             name: "Samples"
+///         }
 ///     }
 ///     down: {
 ///         0: {
@@ -93,6 +127,9 @@ macro_rules! rtt_init_wrappers {
 ///             name: "Terminal"
 ///         }
 ///     }
+///     declared_up: 1 // Channels reserved for declared channels (optional, default: 0)
+///     declared_down: 0
+///     retain: false // Keep buffer contents across resets (optional, default: false)
 ///     section_cb: ".segger_rtt" // Control block linker section (optional, default: no section)
 /// };
 /// ```
@@ -100,13 +137,49 @@ macro_rules! rtt_init_wrappers {
 /// The channel numbers must start from 0 and not skip any numbers, or otherwise odd things will
 /// happen. The order of the channel parameters is fixed, but optional parameters can be left out.
 ///
+/// Instead of a size, an existing buffer can be given with `buffer`, such as a `&'static mut [u8]`
+/// or a `&'static mut MaybeUninit<[u8; N]>`. See [`ChannelBuffer`](crate::ChannelBuffer) for the
+/// supported types. The macro panics if the buffer is too small. The `section` option does not
+/// apply to existing buffers.
+///
 /// If no size is given, then an RTT channel header entry will be allocated with its buffer pointer
 /// set to `null`. This allows third-party FFI crates (e.g. [`rtos-trace`][rtos_trace]/
 /// [`systemview-target`][systemview_target]) to identify and initialize the channel with its own
 /// name and buffer from C code.
 ///
-/// Note: Rust crates should rely on channels initialized through `rtt_init!`. That's why this crate
-/// does not provide an API to initialize pre-allocated channels from Rust.
+/// The `declared_up` and `declared_down` parameters reserve channels after the listed ones for
+/// channels that libraries declare with `declare_up_channel!` and `declare_down_channel!`. This
+/// requires the `declared-channels` feature. The declared channels are not included in the returned
+/// struct, but taken by the libraries that declared them instead. The macro panics if fewer
+/// channels are reserved than are declared.
+///
+/// With `retain: true`, data in up channels that the host has not read yet is kept across resets,
+/// so that it can still be read by the host after e.g. a watchdog reset, or by the firmware with
+/// [`UpChannel::read_retained`](crate::UpChannel::read_retained). Down channels always start empty,
+/// so that input from before the reset is not taken as new input. For this to work, the control
+/// block and the buffers must be placed in memory that is not cleared at startup with `section_cb`
+/// and `section`, such as the `.uninit` sections of `cortex-m-rt`:
+///
+/// ```
+/// let channels = rtt_init! {
+///     up: {
+///         0: {
+///             size: 1024,
+///             name: "Terminal",
+///             section: ".uninit.rtt_terminal"
+///         }
+///     }
+///     retain: true
+///     section_cb: ".uninit.rtt"
+/// };
+/// ```
+///
+/// At initialization the previous control block is validated, and the buffer contents are only
+/// kept if the channel layout and buffer addresses match, so a cold boot or a firmware update
+/// starts with empty buffers.
+///
+/// Rust code can initialize pre-allocated channels at runtime with
+/// [`UpChannel::claim`](crate::UpChannel::claim) and [`DownChannel::claim`](crate::DownChannel::claim).
 ///
 /// This macro should be called once within a function, preferably close to the start of your entry
 /// point. The macro must only be called once - if it's called twice in the same program a duplicate
@@ -142,6 +215,9 @@ macro_rules! rtt_init {
     {
         $(up: { $($up:tt)* } )?
         $(down: { $($down:tt)* } )?
+        $(declared_up: $declared_up:literal )?
+        $(declared_down: $declared_down:literal )?
+        $(retain: $retain:literal )?
         $(section_cb: $section_cb:literal )?
     } => {{
         use core::mem::MaybeUninit;
@@ -151,11 +227,16 @@ macro_rules! rtt_init {
         use $crate::DownChannel;
         use $crate::rtt::*;
 
+        const UP_CHANNELS: usize = $crate::rtt_init_repeat!({ 1 + } { 0 }; $($($up)*)?);
+        const DOWN_CHANNELS: usize = $crate::rtt_init_repeat!({ 1 + } { 0 }; $($($down)*)?);
+        const MAX_UP_CHANNELS: usize = UP_CHANNELS $( + $declared_up )?;
+        const MAX_DOWN_CHANNELS: usize = DOWN_CHANNELS $( + $declared_down )?;
+
         #[repr(C)]
         pub struct RttControlBlock {
             header: RttHeader,
-            up_channels: [RttChannel; $crate::rtt_init_repeat!({ 1 + } { 0 }; $($($up)*)?)],
-            down_channels: [RttChannel; $crate::rtt_init_repeat!({ 1 + } { 0 }; $($($down)*)?)],
+            up_channels: [RttChannel; MAX_UP_CHANNELS],
+            down_channels: [RttChannel; MAX_DOWN_CHANNELS],
         }
 
         #[used]
@@ -178,24 +259,42 @@ macro_rules! rtt_init {
             INITIALIZED.borrow(cs).set(true);
         });
 
-        unsafe {
-            ptr::write_bytes(CONTROL_BLOCK.as_mut_ptr(), 0, 1);
+        let retain = false $( || $retain )?;
+
+        let cb = unsafe {
+            // A retained control block is left as is, so that the channels can keep the data in
+            // their buffers.
+            let header = CONTROL_BLOCK.as_ptr().cast::<RttHeader>();
+            if !(retain && RttHeader::is_retained(header, MAX_UP_CHANNELS, MAX_DOWN_CHANNELS)) {
+                ptr::write_bytes(CONTROL_BLOCK.as_mut_ptr(), 0, 1);
+            }
+
+            &mut *CONTROL_BLOCK.as_mut_ptr()
+        };
 
-            let cb = &mut *CONTROL_BLOCK.as_mut_ptr();
+        // Channel parameters are user code, so the channels are initialized outside of an unsafe
+        // block.
+        // Only up channels keep their data, as data left in a down channel would be taken as new
+        // input.
+        $( $crate::rtt_init_channels!(cb.up_channels; init_retained; $($up)*); )?
+        $( $crate::rtt_init_channels!(cb.down_channels; init; $($down)*); )?
 
-            $( $crate::rtt_init_channels!(cb.up_channels; $($up)*); )?
-            $( $crate::rtt_init_channels!(cb.down_channels; $($down)*); )?
+        init_declared_channels(
+            &mut cb.up_channels[UP_CHANNELS..],
+            &mut cb.down_channels[DOWN_CHANNELS..],
+        );
 
+        pub struct Channels {
+            $( pub up: $crate::rtt_init_repeat!({ UpChannel, } {}; $($up)*), )?
+            $( pub down: $crate::rtt_init_repeat!({ DownChannel, } {}; $($down)*), )?
+        }
+
+        unsafe {
             // The header is initialized last to make it less likely an unfinished control block is
             // detected by the host.
 
             cb.header.init(cb.up_channels.len(), cb.down_channels.len());
 
-            pub struct Channels {
-                $( pub up: $crate::rtt_init_repeat!({ UpChannel, } {}; $($up)*), )?
-                $( pub down: $crate::rtt_init_repeat!({ DownChannel, } {}; $($down)*), )?
-            }
-
             Channels {
                 $( up: $crate::rtt_init_wrappers!(cb.up_channels; UpChannel::new; {}; $($up)*), )?
                 $( down: $crate::rtt_init_wrappers!(cb.down_channels; DownChannel::new; {}; $($down)*), )?
@@ -20,10 +20,14 @@
 //! RTT must be initialized at the start of your program using one of the init macros. See the
 //! macros for more details.
 //!
+//! As an alternative to the macros, the control block can be declared as a [`ControlBlock`] static
+//! and initialized with a [`ChannelConfig`] for each channel.
+//!
 //! The initialization macros return channel objects that can be used for writing and reading.
 //! Different channel objects can safely be used concurrently in different contexts without locking.
 //! In an interrupt-based application with realtime constraints you could use a separate channel for
-//! every interrupt context to allow for lock-free logging.
+//! every interrupt context to allow for lock-free logging. Alternatively, a [`SharedUpChannel`] can
+//! be written to from multiple contexts at once without locking.
 //!
 //! # Channels and virtual terminals
 //!
@@ -60,6 +64,16 @@
 //! needed when debugging. That way you will never end up with an application that freezes without a
 //! debugger connected.
 //!
+//! As a middle ground, the [`BlockWithTimeout`](ChannelMode::BlockWithTimeout) mode blocks while
+//! the buffer is full, but only up to a timeout set with [`set_block_timeout`], after which data is
+//! skipped. The [`Adaptive`](ChannelMode::Adaptive) mode blocks for as long as the host keeps
+//! reading from the buffer, and stops blocking once it doesn't read within the timeout. This makes
+//! it suitable for firmware that may run both with and without a debugger attached.
+//!
+//! For flight recorder style logging, the [`OverwriteOldest`](ChannelMode::OverwriteOldest) mode
+//! never blocks and instead discards the oldest data when the buffer is full, so that the buffer
+//! always contains the latest output.
+//!
 //! # Defmt integration
 //!
 //! The `defmt` crate can be used to format messages in a way that is more efficient and more
@@ -70,7 +84,7 @@
 //! ```toml
 //! [dependencies]
 //! defmt = { version = "0.3" }
-//! rtt-target = { version = "0.6", features = ["defmt"] }
+//! rtt-target = { version = "0.7", features = ["defmt"] }
 //! ```
 //!
 //! # Log integration
@@ -90,6 +104,25 @@
 //! }
 //! ```
 //!
+//! Which messages are printed can be changed at runtime with `env_logger` style directives such as
+//! `warn,net::tcp=trace` using [`set_log_filter`], or from the host through a down channel using
+//! [`poll_log_filter`].
+//!
+//! # embedded-io integration
+//!
+//! With the `embedded-io` feature enabled, [`UpChannel`] implements the `embedded_io::Write` and
+//! `WriteReady` traits and [`DownChannel`] implements `embedded_io::Read` and `ReadReady`, so the
+//! channels can be handed to drivers and protocol crates that are generic over those traits.
+//!
+//! Writing follows the channel mode: in the non-blocking modes data that does not fit is discarded
+//! as usual, while in [`BlockIfFull`](ChannelMode::BlockIfFull) mode a write blocks until at least
+//! one byte fits. Reading blocks until at least one byte is available, as the trait requires.
+//!
+//! The `embedded-io-async` feature additionally implements the async versions of `Read` and
+//! `Write`. As the host does not signal when it has read or written data, pending operations check
+//! the channel again according to the [`PollStrategy`] set with [`set_poll_strategy`]. By default
+//! the waiting task is woken immediately, which yields to other tasks but keeps the executor busy.
+//!
 //! # Plain Printing
 //!
 //! For no-hassle output the [`rprint`] and [`rprintln`] macros are provided. They use a single down
@@ -97,6 +130,10 @@
 //! therefore work exactly like the standard `println` style macros. They can be used from any
 //! context. The [`rtt_init_print`] convenience macro initializes printing on channel 0.
 //!
+//! On multi-core chips, each core can print to its own channel instead, which is set up with the
+//! [`rtt_init_print_multicore`] macro or [`set_print_channels`]. This avoids relying on the
+//! critical section to synchronize between cores.
+//!
 //! ```
 //! use rtt_target::{rtt_init_print, rprintln};
 //!
@@ -131,9 +168,20 @@
 //!
 //! The macros also support an extended syntax to print to different RTT virtual terminals.
 //!
+//! Printed lines can be prefixed with timestamps by registering a timestamp provider with
+//! [`set_timestamp`].
+//!
 //! Please note that because a critical section is used, printing into a blocking channel will cause
 //! the application to block and freeze when the buffer is full.
 //!
+//! With the `print-buffer` feature enabled, messages are first formatted into a scratch buffer on
+//! the stack, and then written to the channel in one operation. This keeps the time spent inside
+//! the critical section short, and ensures that a message is either written in full or, in
+//! `NoBlockSkip` mode, not at all. The size of the buffer is 128 bytes by default, and can be set
+//! with the `RTT_PRINT_BUFFER_SIZE` environment variable at build time. Messages that don't fit in
+//! the buffer are truncated, and the part that was cut is counted as dropped in the channel
+//! statistics.
+//!
 //! # Reading
 //!
 //! The following example shows how to set up the RTT to read simple input sent from the host
@@ -155,29 +203,60 @@
 //!     };
 //! }
 //! ```
+//!
+//! For interactive consoles, [`LineEditor`] reads lines from a down channel with support for basic
+//! editing keys and history, and echoes the edits back to the host.
 
 #![no_std]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+use core::cell::Cell;
+use core::cmp::min;
 use core::convert::Infallible;
+use core::ffi::CStr;
 use core::fmt;
+use timeout::Deadline;
 use ufmt_write::uWrite;
 
 #[doc(hidden)]
 /// Public due to access from macro
 pub mod debug;
+#[cfg(feature = "declared-channels")]
+mod declared;
 #[cfg(feature = "defmt")]
 mod defmt;
+#[cfg(feature = "embedded-io")]
+mod embedded_io;
+#[cfg(feature = "embedded-io-async")]
+mod embedded_io_async;
 #[cfg(feature = "log")]
 mod log;
 /// Public due to access from macro
 #[doc(hidden)]
 pub mod rtt;
 
+mod buffer;
+mod channels;
+mod console;
+mod control_block;
+mod grant;
 mod init;
 mod print;
+#[cfg(target_has_atomic = "ptr")]
+mod shared;
+mod terminal;
+mod timeout;
+mod transaction;
 
+pub use buffer::ChannelBuffer;
+pub use channels::{down_channels, up_channels, ChannelInfo, Channels};
+pub use console::{Echo, LineEditor, LineEvent};
+pub use control_block::{ChannelConfig, ControlBlock};
+pub use grant::{ReadGrant, SplitReadGrant, SplitWriteGrant, WriteGrant};
 pub use print::*;
+pub use terminal::TerminalDecoder;
+pub use timeout::{set_block_timeout, BlockTimeout, TickSource};
+pub use transaction::Transaction;
 
 #[cfg(feature = "defmt")]
 pub use defmt::set_defmt_channel;
@@ -185,6 +264,15 @@ pub use defmt::set_defmt_channel;
 #[cfg(feature = "log")]
 pub use log::*;
 
+#[cfg(feature = "embedded-io-async")]
+pub use embedded_io_async::{set_poll_strategy, PollStrategy};
+
+#[cfg(feature = "declared-channels")]
+pub use declared::DeclaredChannel;
+
+#[cfg(target_has_atomic = "ptr")]
+pub use shared::SharedUpChannel;
+
 /// RTT up (target to host) channel
 ///
 /// Supports writing binary data directly, or writing strings via [`core::fmt`] macros such as
@@ -192,7 +280,12 @@ pub use log::*;
 ///
 /// Note that the formatted writing implementations diverge slightly from the trait definitions in
 /// that if the channel is in non-blocking mode, writing will *not* block.
-pub struct UpChannel(*mut rtt::RttChannel);
+pub struct UpChannel {
+    channel: *mut rtt::RttChannel,
+    drops: rtt::DropTracker,
+    host: rtt::HostMonitor,
+    trim: Cell<TrimPolicy>,
+}
 
 unsafe impl Send for UpChannel {}
 
@@ -200,22 +293,92 @@ impl UpChannel {
     /// Public due to access from macro.
     #[doc(hidden)]
     pub unsafe fn new(channel: *mut rtt::RttChannel) -> Self {
-        UpChannel(channel)
+        UpChannel {
+            channel,
+            drops: rtt::DropTracker::default(),
+            host: rtt::HostMonitor::default(),
+            trim: Cell::new(TrimPolicy::Bytes),
+        }
     }
 
     #[allow(clippy::mut_from_ref)]
     fn channel(&self) -> &mut rtt::RttChannel {
-        unsafe { &mut *self.0 }
+        unsafe { &mut *self.channel }
+    }
+
+    /// Creates a writer that accounts dropped data to the channel statistics and trims text
+    /// according to the trim policy.
+    fn writer(&self) -> rtt::RttWriter<'_> {
+        self.channel()
+            .writer()
+            .track_drops(&self.drops)
+            .trim_text(self.trim.get())
     }
 
     /// Writes `buf` to the channel and returns the number of bytes written. Behavior when the
     /// buffer is full is subject to the channel blocking mode.
     pub fn write(&mut self, buf: &[u8]) -> usize {
-        let mut writer = self.channel().writer();
+        let mut writer = self.writer();
         writer.write(buf);
         writer.commit()
     }
 
+    /// Starts a write operation that can be made up of multiple writes, and is committed or
+    /// aborted as a whole. See [`Transaction`].
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction::new(self.writer(), None)
+    }
+
+    /// Reserves exactly `len` contiguous bytes of the buffer for writing directly into. Returns
+    /// `None` if there is not enough contiguous free space at the write position.
+    ///
+    /// Grants never block and are not affected by the channel mode. If the free space wraps around
+    /// the end of the buffer, use [`split_grant`](UpChannel::split_grant) instead.
+    ///
+    /// ```
+    /// let mut output = channels.up.0;
+    /// if let Some(mut grant) = output.grant_exact(4) {
+    ///     grant.buf().copy_from_slice(&sample.to_le_bytes());
+    ///     grant.commit(4);
+    /// }
+    /// ```
+    pub fn grant_exact(&mut self, len: usize) -> Option<WriteGrant<'_>> {
+        let writer = self.channel().writer();
+
+        if writer.writable_regions().0 < len {
+            return None;
+        }
+
+        Some(WriteGrant::new(writer, len))
+    }
+
+    /// Reserves as much contiguous space as is available at the write position, up to `max` bytes.
+    /// Returns `None` if the buffer is full.
+    pub fn grant_max_remaining(&mut self, max: usize) -> Option<WriteGrant<'_>> {
+        let writer = self.channel().writer();
+
+        let len = min(writer.writable_regions().0, max);
+        if len == 0 {
+            return None;
+        }
+
+        Some(WriteGrant::new(writer, len))
+    }
+
+    /// Reserves exactly `len` bytes of the buffer for writing directly into, allowing the region to
+    /// wrap around the end of the buffer. Returns `None` if there is not enough free space.
+    pub fn split_grant(&mut self, len: usize) -> Option<SplitWriteGrant<'_>> {
+        let writer = self.channel().writer();
+
+        let (contiguous, wrapped) = writer.writable_regions();
+        if contiguous + wrapped < len {
+            return None;
+        }
+
+        let len1 = min(contiguous, len);
+        Some(SplitWriteGrant::new(writer, len1, len - len1))
+    }
+
     /// Creates a writer for formatted writing with ufmt.
     ///
     /// The correct way to use this method is to call it once for each write operation. This is so
@@ -226,7 +389,7 @@ impl UpChannel {
     /// uwriteln!(output.u(), "Hello, ufmt!");
     /// ```
     pub fn u(&mut self) -> uWriter {
-        uWriter(self.channel().writer())
+        uWriter(self.writer())
     }
 
     /// Gets the current blocking mode of the channel. The default is `NoBlockSkip`.
@@ -239,6 +402,72 @@ impl UpChannel {
         self.channel().set_mode(mode)
     }
 
+    /// Gets the record delimiter used in [`OverwriteOldest`](ChannelMode::OverwriteOldest) mode.
+    pub fn record_delimiter(&self) -> Option<u8> {
+        self.channel().record_delimiter()
+    }
+
+    /// Sets the byte that terminates records, such as `b'\n'` for lines of text or `0` for
+    /// `defmt` frames. In [`OverwriteOldest`](ChannelMode::OverwriteOldest) mode, data is discarded
+    /// up to and including a delimiter, so that the host never sees half a record at the start of
+    /// the buffer. The default is `None`, in which case data is discarded a byte at a time.
+    pub fn set_record_delimiter(&mut self, delimiter: Option<u8>) {
+        self.channel().set_record_delimiter(delimiter)
+    }
+
+    /// Returns true if the host is reading from the channel, as seen from the read pointer.
+    ///
+    /// While there is data in the buffer, the host must read some of it within the window set with
+    /// [`set_block_timeout`], counting from the first call that finds the read pointer unchanged.
+    /// With [`BlockTimeout::Spins`], the window is counted in calls to this method, so a
+    /// [`BlockTimeout::Ticks`] timeout gives more predictable results. An empty buffer counts as
+    /// read by the host.
+    ///
+    /// This is also false if a write in [`Adaptive`](ChannelMode::Adaptive) or
+    /// [`BlockWithTimeout`](ChannelMode::BlockWithTimeout) mode has given up waiting for the host,
+    /// and the host has not read from the channel since.
+    pub fn host_connected(&self) -> bool {
+        self.host.host_connected(self.channel())
+    }
+
+    /// Gets statistics about the data that has been discarded because the buffer was full.
+    pub fn stats(&self) -> ChannelStats {
+        self.drops.stats()
+    }
+
+    /// Resets the statistics returned by [`stats`](UpChannel::stats).
+    pub fn reset_stats(&mut self) {
+        self.drops.reset()
+    }
+
+    /// Sets whether to write a marker such as `<42 bytes dropped>` to the channel when data has
+    /// been discarded. The marker is written in front of the next write that succeeds, so that gaps
+    /// in the output are visible on the host. The default is off.
+    ///
+    /// The marker is plain text, so it should only be enabled for text channels.
+    pub fn set_drop_marker(&mut self, enabled: bool) {
+        self.drops.set_marker(enabled)
+    }
+
+    /// Gets how text is trimmed when it doesn't fit in the buffer. The default is
+    /// [`TrimPolicy::Bytes`].
+    pub fn trim_policy(&self) -> TrimPolicy {
+        self.trim.get()
+    }
+
+    /// Sets how text written with formatted writing is trimmed when it doesn't fit in the buffer in
+    /// [`NoBlockTrim`](ChannelMode::NoBlockTrim) mode. Binary data written with
+    /// [`write`](UpChannel::write) is not affected.
+    ///
+    /// ```
+    /// let mut output = channels.up.0;
+    /// output.set_mode(ChannelMode::NoBlockTrim);
+    /// output.set_trim_policy(TrimPolicy::Lines);
+    /// ```
+    pub fn set_trim_policy(&mut self, policy: TrimPolicy) {
+        self.trim.set(policy)
+    }
+
     /// Converts the channel into a virtual terminal that can be used for writing into multiple
     /// virtual terminals.
     pub fn into_terminal(self) -> TerminalChannel {
@@ -258,27 +487,79 @@ impl UpChannel {
     /// besides the returned object during or after calling this function. Essentially this function
     /// is only safe to use in panic handlers and the like that permanently disable interrupts.
     pub unsafe fn conjure(number: usize) -> Option<UpChannel> {
-        extern "C" {
-            #[link_name = "_SEGGER_RTT"]
-            static mut CONTROL_BLOCK: rtt::RttHeader;
-        }
+        let ptr = rtt::up_channel(number)?;
 
-        let control_block = core::ptr::addr_of_mut!(CONTROL_BLOCK);
-        if number >= (*control_block).max_up_channels() {
+        if !(*ptr).is_initialized() {
             return None;
         }
 
-        let channels = control_block.add(1).cast::<rtt::RttChannel>();
+        Some(UpChannel::new(ptr))
+    }
 
-        // First addition moves to the start of the up channel array, second addition moves to the
-        // correct channel.
-        let ptr = channels.add(number);
+    /// Magically creates a channel out of thin air by looking up the first initialized up channel
+    /// with the given name. Returns `None` if there is no such channel.
+    ///
+    /// ```
+    /// let terminal = unsafe { UpChannel::conjure_by_name("Terminal") };
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// The same rules apply as for [`conjure`](UpChannel::conjure).
+    pub unsafe fn conjure_by_name(name: &str) -> Option<UpChannel> {
+        let info = up_channels().find(|info| info.has_name(name))?;
 
-        if !(*ptr).is_initialized() {
-            return None;
-        }
+        UpChannel::conjure(info.number)
+    }
 
-        Some(UpChannel(ptr))
+    /// Claims the first uninitialized up channel in the control block, such as one pre-allocated
+    /// without a size in [`rtt_init`], and initializes it according to `config`. Returns `None` if
+    /// there are no uninitialized up channels left, or if RTT has not been initialized yet.
+    ///
+    /// This allows libraries to set up their own channels at runtime. Each channel can only be
+    /// claimed once, so channels meant to be initialized by C code must be claimed before any Rust
+    /// code claims channels.
+    ///
+    /// Calling this function will cause a linking error if `rtt_init` has not been called.
+    ///
+    /// ```
+    /// let buffer = cortex_m::singleton!(: [u8; 256] = [0; 256]).unwrap();
+    /// let trace = UpChannel::claim(ChannelConfig::new(buffer).name(c"Trace"));
+    /// ```
+    pub fn claim(config: ChannelConfig) -> Option<UpChannel> {
+        control_block::claim(rtt::up_channel, config).map(|ptr| unsafe { UpChannel::new(ptr) })
+    }
+
+    /// Gets the number of the channel in the control block.
+    pub fn number(&self) -> usize {
+        rtt::up_channel_number(self.channel)
+    }
+
+    /// Gets the name of the channel, or `None` if it has no name.
+    pub fn name(&self) -> Option<&'static CStr> {
+        self.channel().name()
+    }
+
+    /// Gets the name of the channel as a string slice, or `None` if it has no name or the name is
+    /// not valid UTF-8.
+    pub fn name_str(&self) -> Option<&'static str> {
+        self.name().and_then(|name| name.to_str().ok())
+    }
+
+    /// Gets the maximum amount of data the buffer can hold, which is one byte less than its size.
+    pub fn capacity(&self) -> usize {
+        self.channel().size().saturating_sub(1)
+    }
+
+    /// Gets the amount of data in the buffer that the host has not read yet.
+    pub fn used(&self) -> usize {
+        self.channel().available()
+    }
+
+    /// Gets the amount of data that can currently be written without blocking or discarding
+    /// anything.
+    pub fn free(&self) -> usize {
+        self.channel().free()
     }
 
     /// Returns true if the channel is empty.
@@ -287,6 +568,21 @@ impl UpChannel {
         write == read
     }
 
+    /// Reads data that is still in the buffer from before a reset, when the buffers are retained
+    /// across resets with `retain: true` in [`rtt_init`]. Returns the number of bytes read.
+    ///
+    /// This should be called before anything is written to the channel, as afterwards the new data
+    /// is returned as well. Data read this way is no longer available to the host.
+    ///
+    /// ```
+    /// let mut buf = [0u8; 256];
+    /// let count = channels.up.0.read_retained(&mut buf);
+    /// store_crash_log(&buf[..count]);
+    /// ```
+    pub fn read_retained(&mut self, buf: &mut [u8]) -> usize {
+        self.channel().read(buf)
+    }
+
     /// Wait until all data has been read by the debugger.
     ///
     /// *Note: This means that if no debugger is connected or if it isn't reading the rtt data,*
@@ -303,11 +599,11 @@ impl UpChannel {
 
 impl fmt::Write for UpChannel {
     fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
-        self.channel().writer().write_str(s)
+        self.writer().write_str(s)
     }
 
     fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<(), fmt::Error> {
-        self.channel().writer().write_fmt(args)
+        self.writer().write_fmt(args)
     }
 }
 
@@ -319,7 +615,7 @@ impl uWrite for uWriter<'_> {
     type Error = Infallible;
 
     fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
-        self.0.write(s.as_bytes());
+        self.0.write_text(s);
         Ok(())
     }
 }
@@ -336,8 +632,74 @@ impl DownChannel {
         DownChannel(channel)
     }
 
-    fn channel(&mut self) -> &mut rtt::RttChannel {
-        unsafe { &mut *self.0 }
+    fn channel(&self) -> &rtt::RttChannel {
+        unsafe { &*self.0 }
+    }
+
+    /// Magically creates a channel out of thin air. Return `None` if the channel number is too
+    /// high, or if the channel has not been initialized.
+    ///
+    /// # Safety
+    ///
+    /// The same rules apply as for [`UpChannel::conjure`].
+    pub unsafe fn conjure(number: usize) -> Option<DownChannel> {
+        let ptr = rtt::down_channel(number)?;
+
+        if !(*ptr).is_initialized() {
+            return None;
+        }
+
+        Some(DownChannel::new(ptr))
+    }
+
+    /// Magically creates a channel out of thin air by looking up the first initialized down
+    /// channel with the given name. Returns `None` if there is no such channel.
+    ///
+    /// # Safety
+    ///
+    /// The same rules apply as for [`UpChannel::conjure`].
+    pub unsafe fn conjure_by_name(name: &str) -> Option<DownChannel> {
+        let info = down_channels().find(|info| info.has_name(name))?;
+
+        DownChannel::conjure(info.number)
+    }
+
+    /// Claims the first uninitialized down channel in the control block and initializes it
+    /// according to `config`. See [`UpChannel::claim`].
+    pub fn claim(config: ChannelConfig) -> Option<DownChannel> {
+        control_block::claim(rtt::down_channel, config).map(|ptr| unsafe { DownChannel::new(ptr) })
+    }
+
+    /// Gets the number of the channel in the control block.
+    pub fn number(&self) -> usize {
+        rtt::down_channel_number(self.0)
+    }
+
+    /// Gets the name of the channel, or `None` if it has no name.
+    pub fn name(&self) -> Option<&'static CStr> {
+        self.channel().name()
+    }
+
+    /// Gets the name of the channel as a string slice, or `None` if it has no name or the name is
+    /// not valid UTF-8.
+    pub fn name_str(&self) -> Option<&'static str> {
+        self.name().and_then(|name| name.to_str().ok())
+    }
+
+    /// Gets the maximum amount of data the buffer can hold, which is one byte less than its size.
+    pub fn capacity(&self) -> usize {
+        self.channel().size().saturating_sub(1)
+    }
+
+    /// Gets the amount of data available for reading. This is the same as
+    /// [`available`](DownChannel::available), for symmetry with [`UpChannel::used`].
+    pub fn used(&self) -> usize {
+        self.available()
+    }
+
+    /// Gets the amount of space left in the buffer for the host to write into.
+    pub fn free(&self) -> usize {
+        self.channel().free()
     }
 
     /// Reads up to `buf.len()` bytes from the channel and return the number of bytes read. This
@@ -345,10 +707,184 @@ impl DownChannel {
     pub fn read(&mut self, buf: &mut [u8]) -> usize {
         self.channel().read(buf)
     }
+
+    /// Reads exactly `buf.len()` bytes from the channel, blocking until enough data has arrived.
+    pub fn read_exact(&mut self, buf: &mut [u8]) {
+        self.read_blocking(None, buf, || false);
+    }
+
+    /// Reads from the channel until `delimiter` has been read or `buf` is full, blocking until
+    /// either happens. Returns the number of bytes read, including the delimiter.
+    ///
+    /// Data following the delimiter is left in the channel.
+    pub fn read_until(&mut self, delimiter: u8, buf: &mut [u8]) -> usize {
+        self.read_blocking(Some(delimiter), buf, || false).count
+    }
+
+    /// Reads a line from the channel into `buf`, blocking until a newline has been read or `buf`
+    /// is full. Returns the number of bytes read, including the newline.
+    pub fn read_line(&mut self, buf: &mut [u8]) -> usize {
+        self.read_until(b'\n', buf)
+    }
+
+    /// Same as [`read_exact`](DownChannel::read_exact), but gives up once `ticks` ticks of `source`
+    /// have passed.
+    pub fn read_exact_timeout(
+        &mut self,
+        buf: &mut [u8],
+        ticks: u32,
+        source: impl TickSource,
+    ) -> TimedRead {
+        let mut deadline = Deadline::new(source, ticks);
+        self.read_blocking(None, buf, || deadline.expired())
+    }
+
+    /// Same as [`read_until`](DownChannel::read_until), but gives up once `ticks` ticks of `source`
+    /// have passed.
+    pub fn read_until_timeout(
+        &mut self,
+        delimiter: u8,
+        buf: &mut [u8],
+        ticks: u32,
+        source: impl TickSource,
+    ) -> TimedRead {
+        let mut deadline = Deadline::new(source, ticks);
+        self.read_blocking(Some(delimiter), buf, || deadline.expired())
+    }
+
+    /// Same as [`read_line`](DownChannel::read_line), but gives up once `ticks` ticks of `source`
+    /// have passed.
+    pub fn read_line_timeout(
+        &mut self,
+        buf: &mut [u8],
+        ticks: u32,
+        source: impl TickSource,
+    ) -> TimedRead {
+        self.read_until_timeout(b'\n', buf, ticks, source)
+    }
+
+    fn read_blocking(
+        &mut self,
+        delimiter: Option<u8>,
+        buf: &mut [u8],
+        mut expired: impl FnMut() -> bool,
+    ) -> TimedRead {
+        let mut count = 0;
+
+        while count < buf.len() {
+            let grant = match self.read_grant() {
+                Some(grant) => grant,
+                None if expired() => {
+                    return TimedRead {
+                        count,
+                        timed_out: true,
+                    }
+                }
+                None => {
+                    core::hint::spin_loop();
+                    continue;
+                }
+            };
+
+            let data = &grant.buf()[..min(grant.len(), buf.len() - count)];
+            let (len, found) = match delimiter.and_then(|d| data.iter().position(|&b| b == d)) {
+                Some(pos) => (pos + 1, true),
+                None => (data.len(), false),
+            };
+
+            buf[count..count + len].copy_from_slice(&data[..len]);
+            grant.release(len);
+            count += len;
+
+            if found {
+                break;
+            }
+        }
+
+        TimedRead {
+            count,
+            timed_out: false,
+        }
+    }
+
+    /// Copies up to `buf.len()` bytes from the channel without removing them, and returns the
+    /// number of bytes copied. This method never blocks.
+    pub fn peek(&self, buf: &mut [u8]) -> usize {
+        self.channel().peek(buf)
+    }
+
+    /// Returns the number of bytes available for reading.
+    pub fn available(&self) -> usize {
+        self.channel().available()
+    }
+
+    /// Borrows the contiguous data available at the read position for reading in place. Returns
+    /// `None` if there is no data available.
+    ///
+    /// Only the data up to the end of the buffer is included. Use
+    /// [`split_read_grant`](DownChannel::split_read_grant) to also get the data that wraps around
+    /// to the start of the buffer.
+    ///
+    /// ```
+    /// let mut input = channels.down.0;
+    /// if let Some(grant) = input.read_grant() {
+    ///     let used = parse_frame(grant.buf());
+    ///     grant.release(used);
+    /// }
+    /// ```
+    pub fn read_grant(&mut self) -> Option<ReadGrant<'_>> {
+        let chan = self.channel();
+
+        let len = chan.readable_regions().0;
+        if len == 0 {
+            return None;
+        }
+
+        Some(ReadGrant::new(chan, len))
+    }
+
+    /// Borrows all data available for reading in place, as two slices if the data wraps around the
+    /// end of the buffer. Returns `None` if there is no data available.
+    pub fn split_read_grant(&mut self) -> Option<SplitReadGrant<'_>> {
+        let chan = self.channel();
+
+        let (contiguous, wrapped) = chan.readable_regions();
+        if contiguous == 0 {
+            return None;
+        }
+
+        Some(SplitReadGrant::new(chan, contiguous, wrapped))
+    }
+}
+
+/// Statistics about the data discarded by an up channel because the buffer was full.
+///
+/// In [`OverwriteOldest`](ChannelMode::OverwriteOldest) mode, older data that is overwritten to
+/// make room for new data is counted as discarded by the write that overwrote it.
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
+pub struct ChannelStats {
+    /// The number of writes that discarded data, either completely or partially.
+    pub dropped_writes: usize,
+
+    /// The total number of bytes discarded.
+    pub dropped_bytes: usize,
+}
+
+/// The outcome of a read with a timeout.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct TimedRead {
+    /// The number of bytes read into the buffer.
+    pub count: usize,
+
+    /// True if the timeout expired before the read completed.
+    pub timed_out: bool,
 }
 
 /// Specifies what to do when a channel doesn't have enough buffer space for a complete write.
-#[derive(Eq, PartialEq)]
+///
+/// More modes may be added in the future, so matching on a mode requires a wildcard arm.
+#[derive(Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
 #[repr(usize)]
 pub enum ChannelMode {
     /// Skip writing the data completely if it doesn't fit in its entirety.
@@ -361,6 +897,60 @@ pub enum ChannelMode {
     /// [`rprintln`], this will cause the application to freeze until the host reads from the
     /// buffer.
     BlockIfFull = 2,
+
+    /// Block (spin) if the buffer is full, but give up after the timeout set with
+    /// [`set_block_timeout`] and skip the rest of the data. After a timeout, writes don't block
+    /// again until the host has read from the buffer, so a missing debugger only delays the
+    /// application once.
+    ///
+    /// This mode only exists on the target side. The host sees the channel as `BlockIfFull`, and
+    /// if the host changes the mode, the timeout no longer applies.
+    BlockWithTimeout,
+
+    /// Block (spin) if the buffer is full as long as the host is reading from the buffer, and skip
+    /// the data otherwise. The host counts as connected if it reads from the buffer within the
+    /// window set with [`set_block_timeout`] whenever the buffer is full. Unlike with
+    /// `BlockWithTimeout`, a slow but active host therefore never causes data to be lost. Whether
+    /// the host is considered connected can be checked with
+    /// [`host_connected`](UpChannel::host_connected).
+    ///
+    /// This mode only exists on the target side. The host sees the channel as `BlockIfFull`.
+    Adaptive,
+
+    /// Never block, and make space for new data by discarding the oldest data that the host has
+    /// not read yet. The buffer therefore always holds the most recent output, which is useful
+    /// for recovering the history leading up to a crash from a RAM dump. Data that doesn't fit in
+    /// the buffer even after discarding everything else is trimmed. The discarded data is counted
+    /// in the channel statistics.
+    ///
+    /// If a record delimiter is set with
+    /// [`set_record_delimiter`](UpChannel::set_record_delimiter), data is discarded a whole record
+    /// at a time, so that the oldest data in the buffer always starts at a record boundary.
+    ///
+    /// The host can still read from the channel while it is being overwritten, but may see
+    /// corrupted data if it reads at the same time. This mode only exists on the target side. The
+    /// host sees the channel as `NoBlockSkip`.
+    OverwriteOldest,
+}
+
+/// Specifies where text written with formatted writing is cut when it doesn't fit in the buffer of
+/// a channel in [`NoBlockTrim`](ChannelMode::NoBlockTrim) mode.
+///
+/// With policies other than `Bytes`, the truncation marker `...` followed by a newline is appended
+/// to text that has been cut, space permitting, so that the next message starts on a new line.
+/// Once text has been cut, the rest of the write operation is discarded.
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
+pub enum TrimPolicy {
+    /// Cut the text at any byte, possibly in the middle of a UTF-8 encoded character.
+    #[default]
+    Bytes,
+
+    /// Cut the text at the last complete character that fits.
+    Chars,
+
+    /// Cut the text after the last complete line that fits. If no line of the message fits in
+    /// full, the text is cut at the last complete character instead.
+    Lines,
 }
 
 /// An up channel that supports writing into multiple virtual terminals within the same buffer.
@@ -373,9 +963,18 @@ pub enum ChannelMode {
 /// the fly, so there is no need to declare them in advance. You could, for example, use different
 /// terminal numbers for messages of different priorities to separate them in a viewer program.
 /// Printing uses a `TerminalChannel` internally.
+///
+/// A switch to virtual terminal `n` is written as the byte `0xFF` followed by the ASCII hex digit
+/// for `n` (`'0'` to `'9'` and `'A'` to `'F'`). To allow arbitrary bytes in the data, a `0xFF` data
+/// byte is escaped by writing it twice. Text is valid UTF-8, which never contains `0xFF`, so text
+/// output is not affected by the escaping. Binary data can be written with
+/// [`TerminalWriter::write_bytes`]. [`TerminalDecoder`] implements the host side of the encoding.
 pub struct TerminalChannel {
     channel: UpChannel,
     current: u8,
+
+    /// Bit mask of the virtual terminals whose last write did not end with a newline
+    mid_line: u16,
 }
 
 impl TerminalChannel {
@@ -383,6 +982,7 @@ impl TerminalChannel {
         Self {
             channel,
             current: 0,
+            mid_line: 0,
         }
     }
 
@@ -395,29 +995,43 @@ impl TerminalChannel {
     ///
     /// [`Write`]: fmt::Write
     pub fn write(&mut self, number: u8) -> TerminalWriter {
-        const TERMINAL_ID: [u8; 16] = *b"0123456789ABCDEF";
+        let (writer, current, mid_line) = self.writer(number);
+
+        TerminalWriter {
+            start: writer.position(),
+            writer,
+            number,
+            current,
+            mid_line,
+        }
+    }
 
-        let mut writer = self.channel.channel().writer();
+    /// Starts a write operation to the virtual terminal specified by `number` that can be made up
+    /// of multiple writes, and is committed or aborted as a whole. See [`Transaction`].
+    pub fn transaction(&mut self, number: u8) -> Transaction<'_> {
+        let (writer, current, _) = self.writer(number);
 
-        if number != self.current {
-            // The terminal switch command must be sent in full so the mode cannot be NoBlockTrim
-            let mode = self.channel.mode();
-            let mode = if mode == ChannelMode::NoBlockTrim {
-                ChannelMode::NoBlockSkip
-            } else {
-                mode
-            };
+        Transaction::new(writer, Some((number, current)))
+    }
 
-            writer.write_with_mode(mode, &[0xff, TERMINAL_ID[(number & 0x0f) as usize]]);
+    /// Creates a writer that starts with a switch to the terminal specified by `number` if
+    /// needed. The current terminal must only be updated once the write has succeeded.
+    fn writer(&mut self, number: u8) -> (rtt::RttWriter<'_>, &mut u8, &mut u16) {
+        let mut writer = self.channel.writer();
 
-            self.current = number;
+        if number != self.current {
+            // The terminal switch command must be sent in full so the mode cannot be NoBlockTrim
+            terminal::write_switch(&mut writer, number);
         }
 
-        TerminalWriter {
-            writer,
-            number,
-            current: &mut self.current,
-        }
+        (writer, &mut self.current, &mut self.mid_line)
+    }
+
+    /// Returns true if nothing has been written to the virtual terminal specified by `number` yet,
+    /// or if the last text written to it with [`write`](TerminalChannel::write) ended with a
+    /// newline.
+    pub fn at_line_start(&self, number: u8) -> bool {
+        self.mid_line & (1 << (number & 0x0f)) == 0
     }
 
     /// Gets the current blocking mode of the channel. The default is `NoBlockSkip`.
@@ -430,6 +1044,82 @@ impl TerminalChannel {
         self.channel.set_mode(mode)
     }
 
+    /// Gets the record delimiter. See [`UpChannel::record_delimiter`].
+    pub fn record_delimiter(&self) -> Option<u8> {
+        self.channel.record_delimiter()
+    }
+
+    /// Sets the record delimiter. See [`UpChannel::set_record_delimiter`].
+    pub fn set_record_delimiter(&mut self, delimiter: Option<u8>) {
+        self.channel.set_record_delimiter(delimiter)
+    }
+
+    /// Returns false if the host is known not to be reading from the channel. See
+    /// [`UpChannel::host_connected`].
+    pub fn host_connected(&self) -> bool {
+        self.channel.host_connected()
+    }
+
+    /// Gets statistics about the data that has been discarded because the buffer was full.
+    pub fn stats(&self) -> ChannelStats {
+        self.channel.stats()
+    }
+
+    /// Resets the statistics returned by [`stats`](TerminalChannel::stats).
+    pub fn reset_stats(&mut self) {
+        self.channel.reset_stats()
+    }
+
+    /// Sets whether to write a marker when data has been discarded. See
+    /// [`UpChannel::set_drop_marker`].
+    pub fn set_drop_marker(&mut self, enabled: bool) {
+        self.channel.set_drop_marker(enabled)
+    }
+
+    /// Gets how text is trimmed when it doesn't fit in the buffer. See
+    /// [`UpChannel::trim_policy`].
+    pub fn trim_policy(&self) -> TrimPolicy {
+        self.channel.trim_policy()
+    }
+
+    /// Sets how text is trimmed when it doesn't fit in the buffer. See
+    /// [`UpChannel::set_trim_policy`].
+    pub fn set_trim_policy(&mut self, policy: TrimPolicy) {
+        self.channel.set_trim_policy(policy)
+    }
+
+    /// Gets the number of the channel in the control block.
+    pub fn number(&self) -> usize {
+        self.channel.number()
+    }
+
+    /// Gets the name of the channel. See [`UpChannel::name`].
+    pub fn name(&self) -> Option<&'static CStr> {
+        self.channel.name()
+    }
+
+    /// Gets the name of the channel as a string slice. See [`UpChannel::name_str`].
+    pub fn name_str(&self) -> Option<&'static str> {
+        self.channel.name_str()
+    }
+
+    /// Gets the maximum amount of data the buffer can hold. See [`UpChannel::capacity`].
+    pub fn capacity(&self) -> usize {
+        self.channel.capacity()
+    }
+
+    /// Gets the amount of data in the buffer that the host has not read yet.
+    pub fn used(&self) -> usize {
+        self.channel.used()
+    }
+
+    /// Gets the amount of data that can currently be written without blocking or discarding
+    /// anything. Note that each write to a virtual terminal other than the current one needs two
+    /// extra bytes for switching terminals.
+    pub fn free(&self) -> usize {
+        self.channel.free()
+    }
+
     /// Returns true if the channel is empty.
     pub fn is_empty(&self) -> bool {
         self.channel.is_empty()
@@ -450,11 +1140,24 @@ pub struct TerminalWriter<'c> {
     writer: rtt::RttWriter<'c>,
     number: u8,
     current: &'c mut u8,
+    mid_line: &'c mut u16,
+
+    /// Write position after the terminal switch, so that the last byte written to the terminal can
+    /// be found
+    start: usize,
+}
+
+impl TerminalWriter<'_> {
+    /// Writes arbitrary bytes to the virtual terminal. `0xFF` bytes are escaped by writing them
+    /// twice, so that the host can tell them apart from terminal switches. See [`TerminalChannel`].
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        terminal::write_escaped(&mut self.writer, bytes);
+    }
 }
 
 impl fmt::Write for TerminalWriter<'_> {
     fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
-        self.writer.write(s.as_bytes());
+        self.writer.write_text(s);
         Ok(())
     }
 }
@@ -463,7 +1166,7 @@ impl uWrite for TerminalWriter<'_> {
     type Error = Infallible;
 
     fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
-        self.writer.write(s.as_bytes());
+        self.writer.write_text(s);
         Ok(())
     }
 }
@@ -472,6 +1175,15 @@ impl Drop for TerminalWriter<'_> {
     fn drop(&mut self) {
         if !self.writer.is_failed() {
             *self.current = self.number;
+
+            // The line is tracked from the data actually written, which differs from the requested
+            // data if it was trimmed.
+            let bit = 1 << (self.number & 0x0f);
+            match self.writer.last_byte_since(self.start) {
+                Some(b'\n') => *self.mid_line &= !bit,
+                Some(_) => *self.mid_line |= bit,
+                None => (),
+            }
         }
     }
 }
@@ -481,4 +1193,7 @@ impl Drop for TerminalWriter<'_> {
 #[doc(hidden)]
 pub mod export {
     pub use critical_section;
+
+    #[cfg(feature = "declared-channels")]
+    pub use linkme;
 }
@@ -1,14 +1,140 @@
-use crate::rprintln;
+use crate::{rprintln, DownChannel};
+use core::cell::{Cell, RefCell};
+use critical_section::Mutex;
+use log::LevelFilter;
 use once_cell::sync::OnceCell;
 
-struct Logger {
-    level_filter: log::LevelFilter,
+/// The maximum length of a log filter set with [`set_log_filter`].
+pub const MAX_LOG_FILTER_LEN: usize = 128;
+
+/// The maximum number of directives in a log filter set with [`set_log_filter`].
+pub const MAX_LOG_DIRECTIVES: usize = 16;
+
+/// Error returned when a log filter is too long, has too many directives or contains an invalid
+/// level.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InvalidLogFilter;
+
+/// A parsed directive, with the module it applies to stored as a range of the filter.
+#[derive(Clone, Copy)]
+struct Directive {
+    start: usize,
+    end: usize,
+    level: LevelFilter,
+}
+
+impl Directive {
+    const EMPTY: Directive = Directive {
+        start: 0,
+        end: 0,
+        level: LevelFilter::Off,
+    };
+}
+
+/// A log filter parsed into its directives, so that messages can be matched without parsing the
+/// filter again.
+struct LogFilter {
+    buf: [u8; MAX_LOG_FILTER_LEN],
+    directives: [Directive; MAX_LOG_DIRECTIVES],
+    count: usize,
+}
+
+impl LogFilter {
+    const fn new() -> Self {
+        LogFilter {
+            buf: [0; MAX_LOG_FILTER_LEN],
+            directives: [Directive::EMPTY; MAX_LOG_DIRECTIVES],
+            count: 0,
+        }
+    }
+
+    /// Parses `filter` into a new filter, and returns it along with the maximum level of its
+    /// directives.
+    fn parse(filter: &str) -> Result<(Self, LevelFilter), InvalidLogFilter> {
+        if filter.len() > MAX_LOG_FILTER_LEN {
+            return Err(InvalidLogFilter);
+        }
+
+        let mut parsed = LogFilter::new();
+        parsed.buf[..filter.len()].copy_from_slice(filter.as_bytes());
+
+        let mut max_level = LevelFilter::Off;
+        for (module, level) in directives(filter) {
+            let level = level.ok_or(InvalidLogFilter)?;
+            if parsed.count == MAX_LOG_DIRECTIVES {
+                return Err(InvalidLogFilter);
+            }
+
+            // Modules are slices of the filter, so their offset in it is their offset in the copy
+            let start = if module.is_empty() {
+                0
+            } else {
+                module.as_ptr() as usize - filter.as_ptr() as usize
+            };
+
+            parsed.directives[parsed.count] = Directive {
+                start,
+                end: start + module.len(),
+                level,
+            };
+            parsed.count += 1;
+
+            max_level = max_level.max(level);
+        }
+
+        Ok((parsed, max_level))
+    }
+
+    /// Gets the level of the most specific directive matching `target`.
+    fn level(&self, target: &str) -> LevelFilter {
+        let target = target.as_bytes();
+
+        let mut level = LevelFilter::Off;
+        let mut best = 0;
+
+        for directive in &self.directives[..self.count] {
+            let module = &self.buf[directive.start..directive.end];
+            let matches = module.is_empty()
+                || target == module
+                || (target.starts_with(module) && target[module.len()..].starts_with(b"::"));
+
+            if matches && module.len() >= best {
+                best = module.len();
+                level = directive.level;
+            }
+        }
+
+        level
+    }
+}
+
+/// Parses the directives of a filter into the module they apply to, which is empty for the
+/// default level, and the level, which is `None` if it is invalid.
+fn directives(filter: &str) -> impl Iterator<Item = (&str, Option<LevelFilter>)> {
+    filter
+        .split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| match directive.split_once('=') {
+            Some((module, level)) => (module.trim(), level.trim().parse().ok()),
+            None => match directive.parse() {
+                Ok(level) => ("", Some(level)),
+                // A module without a level enables all levels
+                Err(_) => (directive, Some(LevelFilter::Trace)),
+            },
+        })
 }
 
+static LOG_FILTER: Mutex<RefCell<LogFilter>> = Mutex::new(RefCell::new(LogFilter::new()));
+
+struct Logger;
+
 impl log::Log for Logger {
     /// Returns if logger is enabled.
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= self.level_filter
+        let level = critical_section::with(|cs| LOG_FILTER.borrow_ref(cs).level(metadata.target()));
+
+        metadata.level() <= level
     }
 
     /// Log the record.
@@ -46,7 +172,7 @@ pub fn init_logger_with_level(level_filter: log::LevelFilter) {
     if LOGGER.get().is_some() {
         return;
     }
-    let logger = LOGGER.get_or_init(|| Logger { level_filter });
+    let logger = LOGGER.get_or_init(|| Logger);
 
     // Use racy init if the feature is enabled or the target doesn't support atomic pointers.
     #[cfg(any(not(target_has_atomic = "ptr"), feature = "log_racy_init"))]
@@ -57,24 +183,135 @@ pub fn init_logger_with_level(level_filter: log::LevelFilter) {
     // Use the default init otherwise.
     #[cfg(all(target_has_atomic = "ptr", not(feature = "log_racy_init")))]
     init_default(logger);
+
+    set_log_filter(level_filter.as_str()).ok();
 }
 
 #[cfg(all(target_has_atomic = "ptr", not(feature = "log_racy_init")))]
 fn init_default(logger: &'static Logger) {
     log::set_logger(logger).ok();
-    log::set_max_level(logger.level_filter);
 }
 
 // # Safety
 //
-// This function will call the unsafe functions [log::set_logger_racy] and
-// [log::set_max_level_racy] if either the feature `log_racy_init` is enabled or the target doesn't
-// support atomic pointers. The [once_cell::OnceCell] should ensure that this is only called
-// once.
+// This function will call the unsafe function [log::set_logger_racy] if either the feature
+// `log_racy_init` is enabled or the target doesn't support atomic pointers. The
+// [once_cell::OnceCell] should ensure that this is only called once.
 #[cfg(any(not(target_has_atomic = "ptr"), feature = "log_racy_init"))]
 unsafe fn init_racy(logger: &'static Logger) {
     log::set_logger_racy(logger).ok();
-    log::set_max_level_racy(logger.level_filter);
+}
+
+fn set_max_level(level: LevelFilter) {
+    // Safety: the maximum level is only set inside a critical section.
+    #[cfg(any(not(target_has_atomic = "ptr"), feature = "log_racy_init"))]
+    unsafe {
+        log::set_max_level_racy(level);
+    }
+
+    #[cfg(all(target_has_atomic = "ptr", not(feature = "log_racy_init")))]
+    log::set_max_level(level);
+}
+
+/// Sets which log messages are printed, using `env_logger` style directives.
+///
+/// The filter is a comma-separated list of directives. A directive is either a level such as
+/// `warn`, which applies to all targets, or a target followed by a level such as `net::tcp=trace`,
+/// which applies to the target and the modules within it. A target without a level enables all
+/// levels for it. The most specific directive matching the target of a message applies, and
+/// messages that match no directive are not printed. The maximum level of the `log` crate is kept
+/// in sync with the filter, so that disabled messages are skipped cheaply.
+///
+/// The filter can be at most [`MAX_LOG_FILTER_LEN`] bytes long with at most
+/// [`MAX_LOG_DIRECTIVES`] directives. It can be changed at any time, also before the logger is
+/// initialized. Note that [`init_logger_with_level`] replaces the filter with the given level.
+///
+/// ```
+/// set_log_filter("warn,net::tcp=trace").unwrap();
+/// ```
+pub fn set_log_filter(filter: &str) -> Result<(), InvalidLogFilter> {
+    let (filter, max_level) = LogFilter::parse(filter)?;
+
+    critical_section::with(|cs| {
+        *LOG_FILTER.borrow_ref_mut(cs) = filter;
+        set_max_level(max_level);
+    });
+
+    Ok(())
+}
+
+/// Sets the log filter from commands sent by the host on `input`, so that the log output can be
+/// adjusted while the application is running. Each line received on the channel is a filter, as
+/// accepted by [`set_log_filter`]. The channel should be used for this purpose only, and its
+/// buffer must be large enough to hold the longest filter including the line terminator.
+///
+/// This method never blocks. Returns `None` if no complete line has been received yet, or the
+/// result of applying the filter otherwise. Lines that are too long are discarded.
+///
+/// ```
+/// let mut log_control = channels.down.1;
+/// loop {
+///     poll_log_filter(&mut log_control);
+///     // ...
+/// }
+/// ```
+///
+/// With `probe-rs`, for example, a filter can be sent by typing it into the terminal of the down
+/// channel.
+pub fn poll_log_filter(input: &mut DownChannel) -> Option<Result<(), InvalidLogFilter>> {
+    // The rest of a line that was too long is discarded as it arrives
+    if critical_section::with(|cs| DISCARDING_LINE.borrow(cs).get()) {
+        if !discard_line(input) {
+            return None;
+        }
+
+        critical_section::with(|cs| DISCARDING_LINE.borrow(cs).set(false));
+    }
+
+    // Room for the line terminator
+    let mut buf = [0u8; MAX_LOG_FILTER_LEN + 2];
+
+    let count = input.peek(&mut buf);
+
+    let len = match buf[..count].iter().position(|&b| b == b'\n') {
+        Some(pos) => pos + 1,
+        None if count == buf.len() => {
+            // The line is too long, so discard it, including the part that hasn't been received yet
+            if !discard_line(input) {
+                critical_section::with(|cs| DISCARDING_LINE.borrow(cs).set(true));
+            }
+
+            return Some(Err(InvalidLogFilter));
+        }
+        None => return None,
+    };
+
+    input.read(&mut buf[..len]);
+
+    let filter = core::str::from_utf8(&buf[..len]).map_err(|_| InvalidLogFilter);
+    Some(filter.and_then(|filter| set_log_filter(filter.trim())))
+}
+
+/// Whether [`poll_log_filter`] is discarding the rest of a line that was too long.
+static DISCARDING_LINE: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Discards the received data up to and including the next newline. Returns false if no newline
+/// has been received yet.
+fn discard_line(input: &mut DownChannel) -> bool {
+    while let Some(grant) = input.read_grant() {
+        match grant.iter().position(|&b| b == b'\n') {
+            Some(pos) => {
+                grant.release(pos + 1);
+                return true;
+            }
+            None => {
+                let len = grant.len();
+                grant.release(len);
+            }
+        }
+    }
+
+    false
 }
 
 /// Initializes RTT with a single up channel, sets it as the print channel for the printing macros
@@ -109,3 +346,72 @@ macro_rules! rtt_init_log {
         $crate::rtt_init_log!(Trace, NoBlockSkip, 1024);
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    fn level(filter: &str, target: &str) -> LevelFilter {
+        LogFilter::parse(filter).unwrap().0.level(target)
+    }
+
+    #[test]
+    fn empty_filter_disables_all() {
+        let (filter, max_level) = LogFilter::parse("").unwrap();
+
+        assert_eq!(max_level, LevelFilter::Off);
+        assert_eq!(filter.level("app"), LevelFilter::Off);
+    }
+
+    #[test]
+    fn default_level() {
+        assert_eq!(level("warn", "app"), LevelFilter::Warn);
+        assert_eq!(level("warn", "app::net"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn most_specific_directive_applies() {
+        let filter = "a=warn,a::b=trace";
+
+        assert_eq!(level(filter, "a"), LevelFilter::Warn);
+        assert_eq!(level(filter, "a::c"), LevelFilter::Warn);
+        assert_eq!(level(filter, "a::b"), LevelFilter::Trace);
+        assert_eq!(level(filter, "a::b::c"), LevelFilter::Trace);
+        assert_eq!(level(filter, "b"), LevelFilter::Off);
+
+        assert_eq!(LogFilter::parse(filter).unwrap().1, LevelFilter::Trace);
+    }
+
+    #[test]
+    fn module_matches_whole_path_segments() {
+        assert_eq!(level("net=info", "net"), LevelFilter::Info);
+        assert_eq!(level("net=info", "net::tcp"), LevelFilter::Info);
+        assert_eq!(level("net=info", "network"), LevelFilter::Off);
+    }
+
+    #[test]
+    fn module_without_level_enables_all() {
+        assert_eq!(level("error, net ", "net::tcp"), LevelFilter::Trace);
+        assert_eq!(level("error, net ", "app"), LevelFilter::Error);
+    }
+
+    #[test]
+    fn invalid_filters() {
+        assert!(LogFilter::parse("net=loud").is_err());
+
+        assert!(LogFilter::parse(&"a".repeat(MAX_LOG_FILTER_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn too_many_directives() {
+        let filter = "a=info,".repeat(MAX_LOG_DIRECTIVES + 1);
+
+        let max = &filter[..MAX_LOG_DIRECTIVES * 7 - 1];
+        assert!(LogFilter::parse(max).is_ok());
+
+        let over = &filter[..filter.len() - 1];
+        assert!(LogFilter::parse(over).is_err());
+    }
+}
@@ -1,10 +1,23 @@
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::fmt::{self, Write as _};
 use critical_section::Mutex;
 
 use crate::{TerminalChannel, TerminalWriter, UpChannel};
 
-static PRINT_TERMINAL: Mutex<RefCell<Option<TerminalChannel>>> = Mutex::new(RefCell::new(None));
+/// The maximum number of cores supported by [`set_print_channels`].
+pub const MAX_CORES: usize = 4;
+
+type PrintTerminal = Mutex<RefCell<Option<TerminalChannel>>>;
+
+static PRINT_TERMINAL: PrintTerminal = Mutex::new(RefCell::new(None));
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_TERMINAL: PrintTerminal = Mutex::new(RefCell::new(None));
+static CORE_TERMINALS: [PrintTerminal; MAX_CORES] = [NO_TERMINAL; MAX_CORES];
+
+type CoreId = fn() -> usize;
+
+static CORE_ID: Mutex<Cell<Option<CoreId>>> = Mutex::new(Cell::new(None));
 
 /// Sets the channel to use for [`rprint`], [`rprintln`], [`debug_rprint`] and [`debug_rprintln`].
 ///
@@ -14,14 +27,126 @@ static PRINT_TERMINAL: Mutex<RefCell<Option<TerminalChannel>>> = Mutex::new(RefC
 /// [`debug_rprintln`]: crate::debug_rprintln
 pub fn set_print_channel(channel: UpChannel) {
     critical_section::with(|cs| {
-        *PRINT_TERMINAL.borrow_ref_mut(cs) = Some(TerminalChannel::new(UpChannel(channel.0)))
+        *PRINT_TERMINAL.borrow_ref_mut(cs) = Some(TerminalChannel::new(channel));
+        CORE_ID.borrow(cs).set(None);
+    });
+}
+
+/// Sets a separate print channel for each core of a multi-core chip, so that every core prints to
+/// its own channel. `core_id` must return the number of the core it is called on, which is used
+/// as an index into `channels`. Messages printed on cores without a channel are discarded.
+///
+/// As each core only accesses its own channel, printing is safe even if the critical section
+/// implementation only disables interrupts on the current core. This must be called before the
+/// other cores start printing.
+///
+/// See also [`rtt_init_print_multicore`](crate::rtt_init_print_multicore).
+///
+/// # Panics
+///
+/// Panics if there are more than [`MAX_CORES`] channels.
+pub fn set_print_channels<const N: usize>(core_id: fn() -> usize, channels: [UpChannel; N]) {
+    assert!(N <= MAX_CORES, "too many cores");
+
+    critical_section::with(|cs| {
+        for (terminal, channel) in CORE_TERMINALS.iter().zip(channels) {
+            *terminal.borrow_ref_mut(cs) = Some(TerminalChannel::new(channel));
+        }
+
+        CORE_ID.borrow(cs).set(Some(core_id));
     });
 }
 
-/// Allows accessing the currently set print channel.
+/// Specifies how timestamps are formatted. See [`Timestamp`].
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum TimestampFormat {
+    /// The raw tick count, such as `[1234567]`.
+    Ticks,
+
+    /// Seconds with microsecond precision, such as `[1.234567]`.
+    Micros,
+
+    /// Hours, minutes, seconds and milliseconds, such as `[00:00:01.234]`.
+    Time,
+}
+
+/// A timestamp provider for prefixing printed lines with timestamps. See [`set_timestamp`].
+#[derive(Clone, Copy)]
+pub struct Timestamp {
+    /// Returns the current tick count, such as the value of a free-running timer or the number of
+    /// ticks since startup.
+    pub now: fn() -> u64,
+
+    /// The number of ticks per second. This is not used with [`TimestampFormat::Ticks`].
+    pub ticks_per_second: u64,
+
+    /// How the timestamp is formatted.
+    pub format: TimestampFormat,
+}
+
+impl Timestamp {
+    fn write(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        let ticks = (self.now)();
+
+        if self.format == TimestampFormat::Ticks || self.ticks_per_second == 0 {
+            return write!(w, "[{}] ", ticks);
+        }
+
+        // Computed in u64 to avoid pulling in 128-bit division on small targets
+        let secs = ticks / self.ticks_per_second;
+        let micros = ticks % self.ticks_per_second * 1_000_000 / self.ticks_per_second;
+
+        match self.format {
+            TimestampFormat::Micros => write!(w, "[{}.{:06}] ", secs, micros),
+            _ => write!(
+                w,
+                "[{:02}:{:02}:{:02}.{:03}] ",
+                secs / 3600,
+                secs / 60 % 60,
+                secs % 60,
+                micros / 1000
+            ),
+        }
+    }
+}
+
+static TIMESTAMP: Mutex<Cell<Option<Timestamp>>> = Mutex::new(Cell::new(None));
+
+/// Sets a timestamp provider for prefixing the messages printed with [`rprint`] and [`rprintln`]
+/// with timestamps, or disables timestamps with `None`. This also applies to the `log` backend,
+/// which prints with `rprintln`. Timestamps are disabled by default.
+///
+/// A timestamp is written in front of each message that starts a new line, so that a line printed
+/// in multiple parts only gets a timestamp at the start. Lines are tracked separately for each
+/// virtual terminal.
+///
+/// ```
+/// set_timestamp(Some(Timestamp {
+///     now: || monotonic_ticks(),
+///     ticks_per_second: 1_000_000,
+///     format: TimestampFormat::Time,
+/// }));
+/// ```
+///
+/// [`rprint`]: crate::rprint
+/// [`rprintln`]: crate::rprintln
+pub fn set_timestamp(timestamp: Option<Timestamp>) {
+    critical_section::with(|cs| TIMESTAMP.borrow(cs).set(timestamp));
+}
+
+/// Allows accessing the currently set print channel. If separate channels have been set for each
+/// core, the channel of the current core is used.
 pub fn with_terminal_channel<F: Fn(&mut TerminalChannel)>(f: F) {
     critical_section::with(|cs| {
-        if let Some(term) = &mut *PRINT_TERMINAL.borrow_ref_mut(cs) {
+        let terminal = match CORE_ID.borrow(cs).get() {
+            Some(core_id) => match CORE_TERMINALS.get(core_id()) {
+                Some(terminal) => terminal,
+                None => return,
+            },
+            None => &PRINT_TERMINAL,
+        };
+
+        if let Some(term) = &mut *terminal.borrow_ref_mut(cs) {
             f(term)
         }
     });
@@ -33,7 +158,18 @@ pub mod print_impl {
     use super::*;
 
     fn with_writer<F: Fn(TerminalWriter)>(number: u8, f: F) {
-        with_terminal_channel(|term| f(term.write(number)));
+        let timestamp = critical_section::with(|cs| TIMESTAMP.borrow(cs).get());
+
+        with_terminal_channel(|term| {
+            let line_start = term.at_line_start(number);
+            let mut w = term.write(number);
+
+            if let (Some(timestamp), true) = (timestamp, line_start) {
+                timestamp.write(&mut w).ok();
+            }
+
+            f(w)
+        });
     }
 
     /// Public due to access from macro.
@@ -47,10 +183,88 @@ pub mod print_impl {
     /// Public due to access from macro.
     #[doc(hidden)]
     pub fn write_fmt(number: u8, arg: fmt::Arguments) {
+        // Format the message before entering the critical section, so that it can be written in
+        // one go. Messages that don't fit in the buffer are truncated rather than formatted again,
+        // and the part that was cut is counted as dropped.
+        #[cfg(feature = "print-buffer")]
+        {
+            let mut buf = PrintBuffer::new();
+            buf.write_fmt(arg).ok();
+
+            with_writer(number, |mut w| {
+                w.write_str(buf.as_str()).ok();
+
+                if buf.dropped > 0 && buf.ends_line {
+                    // Keep the newline, so that the next message starts on a new line
+                    w.write_str("\n").ok();
+                    w.writer.add_dropped(buf.dropped - 1);
+                } else {
+                    w.writer.add_dropped(buf.dropped);
+                }
+            });
+        }
+
+        #[cfg(not(feature = "print-buffer"))]
         with_writer(number, |mut w| {
             w.write_fmt(arg).ok();
         });
     }
+
+    #[cfg(feature = "print-buffer")]
+    include!(concat!(env!("OUT_DIR"), "/print_buffer_size.rs"));
+
+    /// A scratch buffer for formatting a message, set with the `RTT_PRINT_BUFFER_SIZE` environment
+    /// variable at build time.
+    #[cfg(feature = "print-buffer")]
+    struct PrintBuffer {
+        buf: [u8; PRINT_BUFFER_SIZE],
+        len: usize,
+
+        /// Number of bytes that didn't fit in the buffer
+        dropped: usize,
+
+        /// Whether the formatted message ends with a newline
+        ends_line: bool,
+    }
+
+    #[cfg(feature = "print-buffer")]
+    impl PrintBuffer {
+        fn new() -> Self {
+            PrintBuffer {
+                buf: [0; PRINT_BUFFER_SIZE],
+                len: 0,
+                dropped: 0,
+                ends_line: false,
+            }
+        }
+
+        fn as_str(&self) -> &str {
+            // Safety: the buffer only contains whole strings.
+            unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+        }
+    }
+
+    #[cfg(feature = "print-buffer")]
+    impl fmt::Write for PrintBuffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if s.is_empty() {
+                return Ok(());
+            }
+
+            // Cut the string at a character boundary if it doesn't fit. Formatting continues, so
+            // that the number of bytes dropped is known.
+            let mut len = core::cmp::min(s.len(), PRINT_BUFFER_SIZE - self.len);
+            while !s.is_char_boundary(len) {
+                len -= 1;
+            }
+
+            self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+            self.len += len;
+            self.dropped += s.len() - len;
+            self.ends_line = s.ends_with('\n');
+            Ok(())
+        }
+    }
 }
 
 /// Prints to the print RTT channel. Works just like the standard `print`.
@@ -183,3 +397,52 @@ macro_rules! rtt_init_print {
         $crate::rtt_init_print!(NoBlockSkip, 1024);
     }};
 }
+
+/// Initializes RTT with an up channel for each core of a dual-core chip, and sets them as the
+/// print channels for the printing macros. Core 0 prints to channel 0 and core 1 to channel 1.
+///
+/// The first argument is a function returning the number of the current core, such as reading the
+/// SIO CPUID register on the RP2040. The optional arguments specify the blocking mode (default:
+/// `NoBlockSkip`) and size of each buffer in bytes (default: 1024). This must be called once
+/// before the second core is started. See [`set_print_channels`] for more details, and for chips
+/// with more cores.
+///
+/// ```
+/// fn core_id() -> usize {
+///     unsafe { (0xd000_0000 as *const u32).read_volatile() as usize }
+/// }
+///
+/// rtt_init_print_multicore!(core_id);
+/// ```
+///
+/// [`set_print_channels`]: crate::set_print_channels
+#[macro_export]
+macro_rules! rtt_init_print_multicore {
+    ($core_id:expr, $mode:path, $size:expr) => {{
+        let channels = $crate::rtt_init! {
+            up: {
+                0: {
+                    size: $size,
+                    mode: $mode,
+                    name: "Terminal"
+                }
+                1: {
+                    size: $size,
+                    mode: $mode,
+                    name: "Terminal 1"
+                }
+            }
+        };
+
+        $crate::set_print_channels($core_id, [channels.up.0, channels.up.1]);
+    }};
+
+    ($core_id:expr, $mode:path) => {
+        $crate::rtt_init_print_multicore!($core_id, $mode, 1024);
+    };
+
+    ($core_id:expr) => {{
+        use $crate::ChannelMode::NoBlockSkip;
+        $crate::rtt_init_print_multicore!($core_id, NoBlockSkip, 1024);
+    }};
+}
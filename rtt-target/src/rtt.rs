@@ -2,10 +2,14 @@
 //! in user code, and therefore mostly undocumented. The module is only public so that it can be
 //! accessed from the rtt_init! macro.
 
-use crate::ChannelMode;
+use crate::timeout::Timeout;
+use crate::{ChannelMode, ChannelStats, TrimPolicy};
+use core::cell::Cell;
 use core::cmp::min;
+use core::ffi::CStr;
 use core::fmt;
 use core::ptr;
+use core::slice;
 use portable_atomic::{AtomicUsize, Ordering::SeqCst};
 
 // Note: this is zero-initialized in the initialization macro so all zeros must be a valid value
@@ -19,7 +23,18 @@ pub struct RttHeader {
     // down_channels: [Channel; down_up_channels]
 }
 
+const MAGIC_STR_BACKWARDS: &[u8; 16] = b"\0\0\0\0\0\0TTR REGGES";
+
 impl RttHeader {
+    /// Creates an uninitialized header, which is the same as a zero-initialized one.
+    pub(crate) const fn new() -> Self {
+        RttHeader {
+            id: [0; 16],
+            max_up_channels: 0,
+            max_down_channels: 0,
+        }
+    }
+
     /// Initializes the control block header.
     ///
     /// # Safety
@@ -32,18 +47,168 @@ impl RttHeader {
         // Copy the ID backward to avoid storing the magic string in the binary. The ID is
         // written backwards to make it less likely an unfinished control block is detected by the host.
 
-        const MAGIC_STR_BACKWARDS: &[u8; 16] = b"\0\0\0\0\0\0TTR REGGES";
-
         for (idx, byte) in MAGIC_STR_BACKWARDS.into_iter().enumerate() {
             ptr::write_volatile(&mut self.id[15 - idx], *byte);
         }
     }
 
+    /// Returns true if the header at `header` has already been initialized with the given numbers
+    /// of channels, as is the case after a reset if the control block is placed in memory that is
+    /// not cleared at startup.
+    ///
+    /// # Safety
+    ///
+    /// `header` must point to memory that is valid for reads.
+    pub unsafe fn is_retained(
+        header: *const RttHeader,
+        max_up_channels: usize,
+        max_down_channels: usize,
+    ) -> bool {
+        RttHeader::has_id(header)
+            && ptr::read_volatile(ptr::addr_of!((*header).max_up_channels)) == max_up_channels
+            && ptr::read_volatile(ptr::addr_of!((*header).max_down_channels)) == max_down_channels
+    }
+
+    /// Returns true if the header at `header` contains the control block ID, which is written last
+    /// when the control block is initialized.
+    ///
+    /// # Safety
+    ///
+    /// `header` must point to memory that is valid for reads.
+    unsafe fn has_id(header: *const RttHeader) -> bool {
+        let id = ptr::read_volatile(ptr::addr_of!((*header).id));
+
+        MAGIC_STR_BACKWARDS
+            .iter()
+            .enumerate()
+            .all(|(idx, byte)| id[15 - idx] == *byte)
+    }
+
     pub fn max_up_channels(&self) -> usize {
         self.max_up_channels
     }
 }
 
+#[cfg(feature = "declared-channels")]
+pub use crate::declared::{DECLARED_DOWN_CHANNELS, DECLARED_UP_CHANNELS};
+
+/// Converts and validates a buffer given to `rtt_init`.
+pub fn channel_buffer(buffer: impl crate::ChannelBuffer) -> *mut [u8] {
+    crate::buffer::into_raw(buffer)
+}
+
+/// Initializes the channels declared with `declare_up_channel` and `declare_down_channel` in the
+/// given channels, which are reserved for them in `rtt_init`. Reserved channels that are not
+/// declared are reset.
+///
+/// # Panics
+///
+/// Panics if more channels are declared than are reserved.
+pub fn init_declared_channels(up_channels: &mut [RttChannel], down_channels: &mut [RttChannel]) {
+    #[cfg(feature = "declared-channels")]
+    crate::declared::init(up_channels, down_channels);
+
+    #[cfg(not(feature = "declared-channels"))]
+    for channel in up_channels.iter_mut().chain(down_channels.iter_mut()) {
+        channel.reset();
+    }
+}
+
+extern "C" {
+    #[link_name = "_SEGGER_RTT"]
+    static mut CONTROL_BLOCK: RttHeader;
+}
+
+/// Gets a pointer to up channel `number` in the control block, or `None` if the number is too high
+/// or the control block has not been initialized yet.
+///
+/// Calling this function will cause a linking error if the control block has not been defined with
+/// `rtt_init` or `ControlBlock`.
+pub(crate) fn up_channel(number: usize) -> Option<*mut RttChannel> {
+    unsafe {
+        let header = ptr::addr_of_mut!(CONTROL_BLOCK);
+
+        // The memory may not have been cleared yet, so the channel counts can only be trusted once
+        // the ID has been written.
+        if !RttHeader::has_id(header) {
+            return None;
+        }
+
+        let max_up_channels = ptr::read_volatile(ptr::addr_of!((*header).max_up_channels));
+
+        if number >= max_up_channels {
+            return None;
+        }
+
+        // The channels follow the header in memory
+        Some(header.add(1).cast::<RttChannel>().add(number))
+    }
+}
+
+/// Gets a pointer to down channel `number` in the control block. See [`up_channel`].
+pub(crate) fn down_channel(number: usize) -> Option<*mut RttChannel> {
+    unsafe {
+        let header = ptr::addr_of_mut!(CONTROL_BLOCK);
+
+        if !RttHeader::has_id(header) {
+            return None;
+        }
+
+        let max_up_channels = ptr::read_volatile(ptr::addr_of!((*header).max_up_channels));
+        let max_down_channels = ptr::read_volatile(ptr::addr_of!((*header).max_down_channels));
+
+        if number >= max_down_channels {
+            return None;
+        }
+
+        // The down channels follow the up channels in memory
+        Some(
+            header
+                .add(1)
+                .cast::<RttChannel>()
+                .add(max_up_channels + number),
+        )
+    }
+}
+
+/// Gets the number of the up channel at `channel`, which must be in the control block.
+pub(crate) fn up_channel_number(channel: *const RttChannel) -> usize {
+    channel_index(channel)
+}
+
+/// Gets the number of the down channel at `channel`, which must be in the control block.
+pub(crate) fn down_channel_number(channel: *const RttChannel) -> usize {
+    let max_up_channels =
+        unsafe { ptr::read_volatile(ptr::addr_of!(CONTROL_BLOCK.max_up_channels)) };
+
+    channel_index(channel) - max_up_channels
+}
+
+/// Gets the index of `channel` in the array of up and down channels following the header.
+fn channel_index(channel: *const RttChannel) -> usize {
+    let channels = unsafe { ptr::addr_of!(CONTROL_BLOCK).add(1) } as usize;
+
+    (channel as usize - channels) / core::mem::size_of::<RttChannel>()
+}
+
+// The low bits of the flags hold the mode as defined by the RTT protocol, which can also be changed
+// by the host. Modes that only exist on the target side are stored as the closest protocol mode plus
+// an extended mode in the bits above it, which the host leaves alone.
+const MODE_MASK: usize = 0b11;
+const EXTENDED_MODE_SHIFT: usize = 2;
+const EXTENDED_MODE_MASK: usize = 0b111 << EXTENDED_MODE_SHIFT;
+const EXTENDED_MODE_BLOCK_WITH_TIMEOUT: usize = 1;
+const EXTENDED_MODE_ADAPTIVE: usize = 2;
+const EXTENDED_MODE_OVERWRITE_OLDEST: usize = 3;
+
+/// Set when a write has given up waiting for the host to read from the channel
+const FLAG_HOST_STALLED: usize = 1 << 5;
+
+// The record delimiter used in OverwriteOldest mode, if set
+const RECORD_DELIMITER_SHIFT: usize = 8;
+const RECORD_DELIMITER_MASK: usize = 0xff << RECORD_DELIMITER_SHIFT;
+const FLAG_RECORD_DELIMITER: usize = 1 << 16;
+
 // Note: this is zero-initialized in the initialization macro so all zeros must be a valid value
 #[repr(C)]
 pub struct RttChannel {
@@ -56,43 +221,196 @@ pub struct RttChannel {
 }
 
 impl RttChannel {
-    /// Initializes the channel.
+    /// Creates an uninitialized channel, which is the same as a zero-initialized one.
+    pub(crate) const fn new() -> Self {
+        RttChannel {
+            name: ptr::null(),
+            buffer: ptr::null_mut(),
+            size: 0,
+            write: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            flags: AtomicUsize::new(0),
+        }
+    }
+
+    /// Initializes the channel with an empty buffer.
     ///
     /// # Safety
     ///
     /// The pointer arguments must point to a valid null-terminated name and writable buffer.
     pub unsafe fn init(&mut self, name: *const u8, mode: ChannelMode, buffer: *mut [u8]) {
+        self.write.store(0, SeqCst);
+        self.read.store(0, SeqCst);
+
+        self.init_fields(name, mode, buffer);
+    }
+
+    /// Initializes an up channel. If the channel already uses the same buffer and its pointers are
+    /// valid, such as after a reset when the control block and buffer are retained, the pointers
+    /// are kept so that the data in the buffer is preserved. Otherwise the buffer is emptied.
+    ///
+    /// This is only meant for up channels, as data left in a down channel would be taken as new
+    /// input from the host.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`init`](RttChannel::init).
+    pub unsafe fn init_retained(&mut self, name: *const u8, mode: ChannelMode, buffer: *mut [u8]) {
+        let size = (&(*buffer)).len();
+
+        let retained = ptr::read_volatile(&self.buffer) == buffer as *mut u8
+            && ptr::read_volatile(&self.size) == size
+            && self.write.load(SeqCst) < size
+            && self.read.load(SeqCst) < size;
+
+        if !retained {
+            self.write.store(0, SeqCst);
+            self.read.store(0, SeqCst);
+        }
+
+        self.init_fields(name, mode, buffer);
+    }
+
+    unsafe fn init_fields(&mut self, name: *const u8, mode: ChannelMode, buffer: *mut [u8]) {
         ptr::write_volatile(&mut self.name, name);
         ptr::write_volatile(&mut self.size, (&(*buffer)).len());
+
+        // Flags left over from before a reset, such as a record delimiter, are not retained
+        self.flags.store(0, SeqCst);
         self.set_mode(mode);
 
         // Set buffer last as it can be used to detect if the channel has been initialized
         ptr::write_volatile(&mut self.buffer, buffer as *mut u8);
     }
 
+    /// Resets the channel to an uninitialized one, such as a pre-allocated channel that was left
+    /// initialized in a retained control block.
+    pub fn reset(&mut self) {
+        // Clear buffer first so that the channel is no longer detected as initialized
+        unsafe {
+            ptr::write_volatile(&mut self.buffer, ptr::null_mut());
+            ptr::write_volatile(self, RttChannel::new());
+        }
+    }
+
+    /// Gets the name of the channel, or `None` if it has no name.
+    pub(crate) fn name(&self) -> Option<&'static CStr> {
+        let name = unsafe { ptr::read_volatile(&self.name) };
+        if name.is_null() {
+            return None;
+        }
+
+        // Safety: channel names are null-terminated strings that live forever.
+        Some(unsafe { CStr::from_ptr(name.cast()) })
+    }
+
     /// Returns true on a non-null value of the (raw) buffer pointer
     pub fn is_initialized(&self) -> bool {
         !self.buffer.is_null()
     }
 
     pub(crate) fn mode(&self) -> ChannelMode {
-        let mode = self.flags.load(SeqCst) & 3;
+        let flags = self.flags.load(SeqCst);
+        let extended = (flags & EXTENDED_MODE_MASK) >> EXTENDED_MODE_SHIFT;
 
-        match mode {
-            0 => ChannelMode::NoBlockSkip,
-            1 => ChannelMode::NoBlockTrim,
-            2 => ChannelMode::BlockIfFull,
+        match (flags & MODE_MASK, extended) {
+            (2, EXTENDED_MODE_BLOCK_WITH_TIMEOUT) => ChannelMode::BlockWithTimeout,
+            (2, EXTENDED_MODE_ADAPTIVE) => ChannelMode::Adaptive,
+            (0, EXTENDED_MODE_OVERWRITE_OLDEST) => ChannelMode::OverwriteOldest,
+            (0, _) => ChannelMode::NoBlockSkip,
+            (1, _) => ChannelMode::NoBlockTrim,
+            (2, _) => ChannelMode::BlockIfFull,
             _ => ChannelMode::NoBlockSkip,
         }
     }
 
     pub(crate) fn set_mode(&self, mode: ChannelMode) {
+        let (mode, extended) = match mode {
+            ChannelMode::NoBlockSkip => (0, 0),
+            ChannelMode::NoBlockTrim => (1, 0),
+            ChannelMode::BlockIfFull => (2, 0),
+            ChannelMode::BlockWithTimeout => (2, EXTENDED_MODE_BLOCK_WITH_TIMEOUT),
+            ChannelMode::Adaptive => (2, EXTENDED_MODE_ADAPTIVE),
+            ChannelMode::OverwriteOldest => (0, EXTENDED_MODE_OVERWRITE_OLDEST),
+        };
+
+        let flags = self.flags.load(SeqCst) & !(MODE_MASK | EXTENDED_MODE_MASK | FLAG_HOST_STALLED);
         self.flags
-            .store((self.flags.load(SeqCst) & !3) | mode as usize, SeqCst);
+            .store(flags | mode | (extended << EXTENDED_MODE_SHIFT), SeqCst);
+    }
+
+    pub(crate) fn record_delimiter(&self) -> Option<u8> {
+        let flags = self.flags.load(SeqCst);
+
+        if flags & FLAG_RECORD_DELIMITER != 0 {
+            Some(((flags & RECORD_DELIMITER_MASK) >> RECORD_DELIMITER_SHIFT) as u8)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn set_record_delimiter(&self, delimiter: Option<u8>) {
+        let delimiter = match delimiter {
+            Some(byte) => FLAG_RECORD_DELIMITER | ((byte as usize) << RECORD_DELIMITER_SHIFT),
+            None => 0,
+        };
+
+        let flags = self.flags.load(SeqCst) & !(RECORD_DELIMITER_MASK | FLAG_RECORD_DELIMITER);
+        self.flags.store(flags | delimiter, SeqCst);
+    }
+
+    /// Returns true if a write has given up waiting for the host, and the host has not read from
+    /// the channel since.
+    pub(crate) fn is_host_stalled(&self) -> bool {
+        self.flags.load(SeqCst) & FLAG_HOST_STALLED != 0
+    }
+
+    /// Returns false if a write has given up waiting for the host and the host has not read from
+    /// the channel since. See also `HostMonitor`.
+    pub(crate) fn host_connected(&self) -> bool {
+        if !self.is_host_stalled() {
+            return true;
+        }
+
+        // The buffer was full when the write gave up, so any free space means the host has read
+        // from it since.
+        if self.free() > 0 {
+            self.set_host_stalled(false);
+            return true;
+        }
+
+        false
+    }
+
+    #[cfg(target_has_atomic = "ptr")]
+    fn set_host_stalled(&self, stalled: bool) {
+        if stalled {
+            self.flags.fetch_or(FLAG_HOST_STALLED, SeqCst);
+        } else {
+            self.flags.fetch_and(!FLAG_HOST_STALLED, SeqCst);
+        }
+    }
+
+    // Without compare-and-swap the flag is updated with a plain store, which can undo a mode
+    // change made by the host at the same time.
+    #[cfg(not(target_has_atomic = "ptr"))]
+    fn set_host_stalled(&self, stalled: bool) {
+        let flags = self.flags.load(SeqCst) & !FLAG_HOST_STALLED;
+        let stalled = if stalled { FLAG_HOST_STALLED } else { 0 };
+        self.flags.store(flags | stalled, SeqCst);
+    }
+
+    // This method should only be called for down channels, or for retained up channels before
+    // anything has been written to them.
+    pub(crate) fn read(&self, buf: &mut [u8]) -> usize {
+        let count = self.peek(buf);
+        self.consume(count);
+
+        count
     }
 
     // This method should only be called for down channels.
-    pub(crate) fn read(&self, mut buf: &mut [u8]) -> usize {
+    pub(crate) fn peek(&self, mut buf: &mut [u8]) -> usize {
         let (write, mut read) = self.read_pointers();
 
         let mut total = 0;
@@ -119,9 +437,51 @@ impl RttChannel {
             buf = &mut buf[count..];
         }
 
+        total
+    }
+
+    /// Advances the read pointer over `count` bytes that have been read.
+    ///
+    /// This method should only be called for down channels.
+    pub(crate) fn consume(&self, count: usize) {
+        let mut read = self.read_pointers().1 + count;
+
+        if read >= self.size {
+            // Wrap around to start
+            read -= self.size;
+        }
+
         self.read.store(read, SeqCst);
+    }
 
-        total
+    /// Gets the data available for reading as two regions: the contiguous data at the read
+    /// pointer, and the data at the start of the buffer that follows it after wrapping around.
+    pub(crate) fn readable_regions(&self) -> (usize, usize) {
+        let (write, read) = self.read_pointers();
+
+        if read > write {
+            (self.readable_contiguous(write, read), write)
+        } else {
+            (self.readable_contiguous(write, read), 0)
+        }
+    }
+
+    /// Gets the buffer region of `len` bytes that starts at the read pointer.
+    ///
+    /// # Safety
+    ///
+    /// The region must be data available for reading as reported by `readable_regions`.
+    pub(crate) unsafe fn buffer_at_read(&self, len: usize) -> &[u8] {
+        slice::from_raw_parts(self.buffer.add(self.read_pointers().1), len)
+    }
+
+    /// Gets the buffer region of `len` bytes that starts at the beginning of the buffer.
+    ///
+    /// # Safety
+    ///
+    /// Same as `buffer_at_read`.
+    pub(crate) unsafe fn buffer_at_start(&self, len: usize) -> &[u8] {
+        slice::from_raw_parts(self.buffer, len)
     }
 
     /// This method should only be called for up channels.
@@ -130,10 +490,67 @@ impl RttChannel {
             chan: self,
             write: self.read_pointers().0,
             total: 0,
+            requested: 0,
+            flushed: false,
             state: WriteState::Writable,
+            drops: None,
+            marker: false,
+            overwritten: 0,
+            trim: TrimPolicy::Bytes,
+            timeout: None,
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Gets the amount of free space for writing at `write`, which may be ahead of the write
+    /// pointer.
+    pub(crate) fn free_at(&self, write: usize) -> usize {
+        let read = self.read_pointers().1;
+
+        if read > write {
+            read - write - 1
+        } else {
+            self.size - write + read - 1
+        }
+    }
+
+    /// Copies `data` into the buffer at `pos`, wrapping around the end of the buffer.
+    ///
+    /// # Safety
+    ///
+    /// The region must be free and must not be written to concurrently.
+    pub(crate) unsafe fn copy_to(&self, pos: usize, data: &[u8]) {
+        let count = min(self.size - pos, data.len());
+
+        ptr::copy_nonoverlapping(data.as_ptr(), self.buffer.add(pos), count);
+        ptr::copy_nonoverlapping(data[count..].as_ptr(), self.buffer, data.len() - count);
+    }
+
+    /// Makes the data up to `write` visible to the host.
+    pub(crate) fn publish(&self, write: usize) {
+        self.write.store(write, SeqCst);
+    }
+
+    /// Gets the total amount of data available for reading
+    pub(crate) fn available(&self) -> usize {
+        let (write, read) = self.read_pointers();
+
+        if read > write {
+            self.size - read + write
+        } else {
+            write - read
         }
     }
 
+    /// Gets the total amount of free space available for writing. One byte is always kept free to
+    /// tell a full buffer apart from an empty one.
+    pub(crate) fn free(&self) -> usize {
+        self.size.saturating_sub(self.available() + 1)
+    }
+
     /// Gets the amount of contiguous data available for reading
     fn readable_contiguous(&self, write: usize, read: usize) -> usize {
         if read > write {
@@ -160,12 +577,145 @@ impl RttChannel {
     }
 }
 
+/// Tracks whether the host is reading from an up channel by watching the read pointer.
+#[derive(Default)]
+pub(crate) struct HostMonitor {
+    /// The read pointer seen at the last check
+    last_read: Cell<usize>,
+
+    /// Window within which the host must read pending data, started when data was first seen
+    /// pending without the read pointer moving
+    window: Cell<Option<Timeout>>,
+}
+
+impl HostMonitor {
+    /// Returns true if the host has read from `chan` within the window set with
+    /// `set_block_timeout`, counting from when data was first seen waiting in the buffer.
+    pub fn host_connected(&self, chan: &RttChannel) -> bool {
+        // A write that gave up waiting for the host overrides the read pointer
+        if !chan.host_connected() {
+            return false;
+        }
+
+        let (write, read) = chan.read_pointers();
+
+        if read != self.last_read.get() || write == read {
+            // The host has read since the last check, or has read everything
+            self.last_read.set(read);
+            self.window.set(None);
+            return true;
+        }
+
+        let mut window = self.window.take().unwrap_or_else(Timeout::start);
+        let expired = window.expired();
+        self.window.set(Some(window));
+
+        !expired
+    }
+}
+
+/// Accounting of data discarded by the writes to an up channel.
+#[derive(Default)]
+pub(crate) struct DropTracker {
+    stats: Cell<ChannelStats>,
+
+    /// Number of bytes dropped since the last drop marker was written
+    pending: Cell<usize>,
+
+    /// Whether to write a drop marker before the next write after data has been dropped
+    marker: Cell<bool>,
+}
+
+impl DropTracker {
+    pub fn stats(&self) -> ChannelStats {
+        self.stats.get()
+    }
+
+    pub fn reset(&self) {
+        self.stats.set(ChannelStats::default());
+        self.pending.set(0);
+    }
+
+    pub fn set_marker(&self, enabled: bool) {
+        self.marker.set(enabled);
+    }
+
+    /// Records a write that dropped `dropped` bytes of its own data and overwrote `overwritten`
+    /// bytes of older data. Overwritten data is not included in the drop marker, as the marker is
+    /// written where the newest data goes, not where the old data was.
+    fn record(&self, dropped: usize, overwritten: usize) {
+        let mut stats = self.stats.get();
+        stats.dropped_writes = stats.dropped_writes.wrapping_add(1);
+        stats.dropped_bytes = stats
+            .dropped_bytes
+            .wrapping_add(dropped)
+            .wrapping_add(overwritten);
+        self.stats.set(stats);
+
+        self.pending.set(self.pending.get().saturating_add(dropped));
+    }
+}
+
+/// Maximum length of a drop marker: the longest `usize` is 20 digits
+const MAX_MARKER_LEN: usize = DROP_MARKER_PREFIX.len() + 20 + DROP_MARKER_SUFFIX.len();
+const DROP_MARKER_PREFIX: &[u8] = b"<";
+const DROP_MARKER_SUFFIX: &[u8] = b" bytes dropped>\n";
+
+/// Appended to text that has been cut according to a `TrimPolicy` other than `Bytes`.
+const TRUNCATION_MARKER: &[u8] = b"...\n";
+
+/// Formats the drop marker for `count` dropped bytes into `buf` and returns its length.
+fn format_drop_marker(mut count: usize, buf: &mut [u8; MAX_MARKER_LEN]) -> usize {
+    let mut digits = [0u8; 20];
+    let mut start = digits.len();
+
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (count % 10) as u8;
+        count /= 10;
+
+        if count == 0 {
+            break;
+        }
+    }
+
+    let mut len = 0;
+    for part in [DROP_MARKER_PREFIX, &digits[start..], DROP_MARKER_SUFFIX] {
+        buf[len..len + part.len()].copy_from_slice(part);
+        len += part.len();
+    }
+
+    len
+}
+
 /// A cancellable write operation to an RTT channel.
 pub(crate) struct RttWriter<'c> {
     chan: &'c RttChannel,
     write: usize,
+
+    /// Number of bytes written, excluding a drop marker
     total: usize,
+
+    /// Number of bytes requested to be written, excluding a drop marker
+    requested: usize,
+
+    /// Whether part of the data has already been made visible to the host while blocking
+    flushed: bool,
+
     state: WriteState,
+    drops: Option<&'c DropTracker>,
+
+    /// Whether a drop marker was written as part of this operation
+    marker: bool,
+
+    /// Number of bytes of older data discarded in `OverwriteOldest` mode to make room
+    overwritten: usize,
+
+    /// How text is trimmed when it doesn't fit in `NoBlockTrim` mode
+    trim: TrimPolicy,
+
+    /// Time left for blocking in `BlockWithTimeout` mode, started when the operation first blocks
+    timeout: Option<Timeout>,
 }
 
 #[derive(Eq, PartialEq)]
@@ -176,16 +726,147 @@ enum WriteState {
     /// Buffer space ran out but the written data will still be committed
     Full,
 
-    /// The operation failed and won't be committed, or it has already been committed.
+    /// The operation failed and won't be committed.
+    Failed,
+
+    /// The operation has been committed or cancelled.
     Finished,
 }
 
-impl RttWriter<'_> {
+impl<'c> RttWriter<'c> {
     pub fn write(&mut self, buf: &[u8]) {
         self.write_with_mode(self.chan.mode(), buf);
     }
 
-    pub fn write_with_mode(&mut self, mode: ChannelMode, mut buf: &[u8]) {
+    pub fn write_with_mode(&mut self, mode: ChannelMode, buf: &[u8]) {
+        self.requested += buf.len();
+        self.write_impl(mode, buf);
+    }
+
+    /// Writes a sequence that must not be split, such as an escape sequence. In `NoBlockTrim` mode
+    /// the operation is cut before the sequence if it doesn't fit in full, or fails if nothing has
+    /// been written yet.
+    pub fn write_unsplit(&mut self, buf: &[u8]) {
+        let mode = self.chan.mode();
+
+        if mode == ChannelMode::NoBlockTrim && self.state == WriteState::Writable {
+            if self.total == 0 {
+                self.write_with_mode(ChannelMode::NoBlockSkip, buf);
+                return;
+            }
+
+            let (contiguous, wrapped) = self.writable_regions();
+            if contiguous + wrapped < buf.len() {
+                self.requested += buf.len();
+                self.state = WriteState::Full;
+                return;
+            }
+        }
+
+        self.write_with_mode(mode, buf);
+    }
+
+    /// Accounts dropped data to `drops`, and writes a drop marker first if data has been dropped
+    /// since the last marker and markers are enabled.
+    pub fn track_drops(mut self, drops: &'c DropTracker) -> Self {
+        self.drops = Some(drops);
+
+        let pending = drops.pending.get();
+        if drops.marker.get() && pending > 0 {
+            let mut buf = [0u8; MAX_MARKER_LEN];
+            let len = format_drop_marker(pending, &mut buf);
+
+            // The marker is only written if it fits in full, and it does not count towards the
+            // number of bytes written.
+            let (contiguous, wrapped) = self.writable_regions();
+            if contiguous + wrapped >= len {
+                self.write_impl(ChannelMode::NoBlockTrim, &buf[..len]);
+                self.total -= len;
+                self.marker = true;
+            }
+        }
+
+        self
+    }
+
+    /// Sets how text written with [`write_text`](Self::write_text) is trimmed.
+    pub fn trim_text(mut self, policy: TrimPolicy) -> Self {
+        self.trim = policy;
+        self
+    }
+
+    /// Writes text. In `NoBlockTrim` mode, text that doesn't fit is cut according to the trim
+    /// policy, after which nothing more is written in this operation.
+    pub fn write_text(&mut self, s: &str) {
+        let mode = self.chan.mode();
+
+        if mode != ChannelMode::NoBlockTrim
+            || self.trim == TrimPolicy::Bytes
+            || self.state != WriteState::Writable
+        {
+            self.write_with_mode(mode, s.as_bytes());
+            return;
+        }
+
+        let buf = s.as_bytes();
+        self.requested += buf.len();
+
+        let (contiguous, wrapped) = self.writable_regions();
+        let free = contiguous + wrapped;
+        if buf.len() <= free {
+            self.write_impl(mode, buf);
+            return;
+        }
+
+        // Leave space for the truncation marker, and back off to a character boundary
+        let mut len = free.saturating_sub(TRUNCATION_MARKER.len());
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        if self.trim == TrimPolicy::Lines {
+            match buf[..len].iter().rposition(|&b| b == b'\n') {
+                Some(pos) => len = pos + 1,
+                None => {
+                    // The line started in an earlier write of this operation
+                    if self.rewind_to_newline() {
+                        len = 0;
+                    }
+                }
+            }
+        }
+
+        self.write_impl(mode, &buf[..len]);
+
+        // The marker does not count towards the number of bytes written, so that the cut text is
+        // accounted as dropped.
+        if free >= TRUNCATION_MARKER.len() {
+            self.write_impl(mode, TRUNCATION_MARKER);
+            self.total -= TRUNCATION_MARKER.len();
+        }
+
+        self.state = WriteState::Full;
+    }
+
+    /// Moves the write position back to just after the last newline written in this operation.
+    /// Returns false if no newline has been written in this operation.
+    fn rewind_to_newline(&mut self) -> bool {
+        let size = self.chan.size;
+
+        for back in 1..=self.total {
+            let pos = (self.write + size - back) % size;
+
+            if unsafe { ptr::read_volatile(self.chan.buffer.add(pos)) } == b'\n' {
+                self.write = (pos + 1) % size;
+                self.total -= back - 1;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn write_impl(&mut self, mode: ChannelMode, mut buf: &[u8]) {
         while self.state == WriteState::Writable && !buf.is_empty() {
             let count = min(self.writable_contiguous(), buf.len());
 
@@ -196,7 +877,7 @@ impl RttWriter<'_> {
                     ChannelMode::NoBlockSkip => {
                         // Mark the entire operation as failed if even one part cannot be written in
                         // full.
-                        self.state = WriteState::Finished;
+                        self.state = WriteState::Failed;
                         return;
                     }
 
@@ -209,9 +890,61 @@ impl RttWriter<'_> {
                     ChannelMode::BlockIfFull => {
                         // Commit everything written so far and spin until more can be written
                         self.chan.write.store(self.write, SeqCst);
+                        self.flushed = true;
                         continue;
                     }
+
+                    ChannelMode::OverwriteOldest => {
+                        // Make space by discarding the oldest data the host hasn't read yet. Data
+                        // written in this operation can't be discarded, so if the operation alone
+                        // doesn't fit in the buffer, write as much as possible.
+                        if self.discard_oldest(buf.len()) {
+                            continue;
+                        }
+
+                        self.state = WriteState::Full;
+                    }
+
+                    ChannelMode::BlockWithTimeout | ChannelMode::Adaptive => {
+                        // Block like BlockIfFull, but only for a limited time. BlockWithTimeout
+                        // limits the total time spent blocking in one operation, whereas Adaptive
+                        // keeps blocking as long as the host keeps reading within each window.
+                        // Once a write has timed out, further writes skip the wait until the host
+                        // reads again, so that a missing host only stalls the application once.
+                        if !self.chan.is_host_stalled() {
+                            self.chan.write.store(self.write, SeqCst);
+                            self.flushed = true;
+
+                            let has_space = if mode == ChannelMode::Adaptive {
+                                self.wait_for_space(&mut Timeout::start())
+                            } else {
+                                let mut timeout =
+                                    self.timeout.take().unwrap_or_else(Timeout::start);
+                                let has_space = self.wait_for_space(&mut timeout);
+                                self.timeout = Some(timeout);
+                                has_space
+                            };
+
+                            if has_space {
+                                continue;
+                            }
+
+                            self.chan.set_host_stalled(true);
+                        }
+
+                        // Fall back to skipping. Data that has already been made visible to the
+                        // host can't be taken back, so in that case the write is trimmed instead.
+                        self.state = if self.flushed {
+                            WriteState::Full
+                        } else {
+                            WriteState::Failed
+                        };
+                        return;
+                    }
                 }
+            } else if self.chan.is_host_stalled() {
+                // The host has read from the channel again
+                self.chan.set_host_stalled(false);
             }
 
             unsafe {
@@ -230,6 +963,87 @@ impl RttWriter<'_> {
         }
     }
 
+    /// Spins until there is space available for writing, or until `timeout` expires. Returns true
+    /// if space became available.
+    fn wait_for_space(&self, timeout: &mut Timeout) -> bool {
+        loop {
+            if self.writable_contiguous() > 0 {
+                return true;
+            }
+
+            if timeout.expired() {
+                return false;
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Discards committed data that the host has not read yet by advancing the read pointer. If
+    /// a record delimiter is set, data is discarded up to and including the next delimiter, so
+    /// that the host never sees a partial record. Otherwise `wanted` bytes are discarded. Returns
+    /// false if there is no committed data left to discard.
+    fn discard_oldest(&mut self, wanted: usize) -> bool {
+        let (write, read) = self.chan.read_pointers();
+        if read == write {
+            return false;
+        }
+
+        let size = self.chan.size;
+
+        let new_read = match self.chan.record_delimiter() {
+            Some(delimiter) => {
+                let mut pos = read;
+
+                loop {
+                    let byte = unsafe { ptr::read_volatile(self.chan.buffer.add(pos)) };
+
+                    pos += 1;
+                    if pos >= size {
+                        pos = 0;
+                    }
+
+                    if byte == delimiter || pos == write {
+                        break pos;
+                    }
+                }
+            }
+            None => {
+                let committed = if write > read {
+                    write - read
+                } else {
+                    size - read + write
+                };
+
+                (read + min(wanted, committed)) % size
+            }
+        };
+
+        // If the host has read from the buffer in the meantime, there is space now anyway, so
+        // nothing is discarded.
+        #[cfg(target_has_atomic = "ptr")]
+        let discarded = self
+            .chan
+            .read
+            .compare_exchange(read, new_read, SeqCst, SeqCst)
+            .is_ok();
+
+        // Without compare-and-swap, the host can move the read pointer between the check and the
+        // store, in which case it is moved back and the host may read some data twice.
+        #[cfg(not(target_has_atomic = "ptr"))]
+        let discarded = self.chan.read.load(SeqCst) == read;
+        #[cfg(not(target_has_atomic = "ptr"))]
+        if discarded {
+            self.chan.read.store(new_read, SeqCst);
+        }
+
+        if discarded {
+            self.overwritten += (new_read + size - read) % size;
+        }
+
+        true
+    }
+
     /// Gets the amount of contiguous space available for writing
     fn writable_contiguous(&self) -> usize {
         let read = self.chan.read_pointers().1;
@@ -243,37 +1057,126 @@ impl RttWriter<'_> {
         }
     }
 
-    pub fn is_failed(&self) -> bool {
-        self.state != WriteState::Finished
+    /// Gets the free space at the write position as two regions: the contiguous space that follows
+    /// the write position, and the space at the start of the buffer that follows it after wrapping
+    /// around.
+    pub fn writable_regions(&self) -> (usize, usize) {
+        let read = self.chan.read_pointers().1;
+
+        if read > self.write || read == 0 {
+            (self.writable_contiguous(), 0)
+        } else {
+            (self.chan.size - self.write, read - 1)
+        }
+    }
+
+    /// Gets the buffer region of `len` bytes that starts at the write position.
+    ///
+    /// # Safety
+    ///
+    /// The region must be free space as reported by `writable_regions`, and there must not be
+    /// other references to it.
+    pub unsafe fn buffer_at_write(&self, len: usize) -> &'c mut [u8] {
+        slice::from_raw_parts_mut(self.chan.buffer.add(self.write), len)
+    }
+
+    /// Gets the buffer region of `len` bytes that starts at the beginning of the buffer.
+    ///
+    /// # Safety
+    ///
+    /// Same as `buffer_at_write`.
+    pub unsafe fn buffer_at_start(&self, len: usize) -> &'c mut [u8] {
+        slice::from_raw_parts_mut(self.chan.buffer, len)
     }
 
-    pub fn commit(mut self) -> usize {
-        self.commit_impl();
+    /// Advances the write position over `count` bytes that have been written directly into the
+    /// buffer.
+    pub fn advance(&mut self, count: usize) {
+        self.write += count;
+        self.total += count;
 
-        self.total
+        if self.write >= self.chan.size {
+            // Wrap around to start
+            self.write -= self.chan.size;
+        }
     }
 
-    fn commit_impl(&mut self) {
-        match self.state {
-            WriteState::Finished => (),
+    /// Cancels the operation. Data that has not been committed yet is discarded. Does nothing if
+    /// the operation has already been committed.
+    pub fn abort(&mut self) {
+        if self.state != WriteState::Finished {
+            self.state = WriteState::Finished;
+            self.total = 0;
+        }
+    }
+
+    /// Accounts `len` bytes that were cut from the data before it was written as dropped.
+    pub fn add_dropped(&mut self, len: usize) {
+        self.requested += len;
+    }
+
+    /// Gets the current write position, which is ahead of the write pointer until the operation
+    /// is committed.
+    pub fn position(&self) -> usize {
+        self.write
+    }
+
+    /// Gets the last byte written in this operation, including markers, or `None` if nothing has
+    /// been written since the write position was `start`.
+    pub fn last_byte_since(&self, start: usize) -> Option<u8> {
+        if self.write == start {
+            return None;
+        }
+
+        let pos = self.write.checked_sub(1).unwrap_or(self.chan.size - 1);
+        Some(unsafe { ptr::read_volatile(self.chan.buffer.add(pos)) })
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.state == WriteState::Failed
+    }
+
+    /// Commits the operation and returns the number of bytes written. Further calls return the
+    /// same count without doing anything.
+    pub fn commit(&mut self) -> usize {
+        let committed = match self.state {
+            WriteState::Finished => return self.total,
+            WriteState::Failed => false,
             WriteState::Full | WriteState::Writable => {
                 // Commit the write pointer so the host can see the new data
                 self.chan.write.store(self.write, SeqCst);
-                self.state = WriteState::Finished;
+                true
+            }
+        };
+
+        self.state = WriteState::Finished;
+
+        let written = if committed { self.total } else { 0 };
+
+        if let Some(drops) = self.drops {
+            if committed && self.marker {
+                drops.pending.set(0);
+            }
+
+            if self.requested > written || self.overwritten > 0 {
+                drops.record(self.requested - written, self.overwritten);
             }
         }
+
+        self.total = written;
+        written
     }
 }
 
 impl Drop for RttWriter<'_> {
     fn drop(&mut self) {
-        self.commit_impl();
+        self.commit();
     }
 }
 
 impl fmt::Write for RttWriter<'_> {
     fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
-        self.write(s.as_bytes());
+        self.write_text(s);
         Ok(())
     }
 }
@@ -0,0 +1,146 @@
+use crate::rtt::RttChannel;
+use crate::{ChannelMode, UpChannel};
+use core::cmp::min;
+use core::ptr;
+use portable_atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering::SeqCst};
+
+// The state packs the position up to which space has been reserved together with the number of
+// writes in progress, so that both can be updated with a single compare-and-swap.
+const IN_FLIGHT_SHIFT: u32 = usize::BITS - 8;
+const IN_FLIGHT_ONE: usize = 1 << IN_FLIGHT_SHIFT;
+const MAX_IN_FLIGHT: usize = 0xff;
+const POSITION_MASK: usize = IN_FLIGHT_ONE - 1;
+
+/// An up channel that can be written to from multiple contexts at once without locking.
+///
+/// Unlike [`UpChannel`], which needs a critical section or other synchronization to be shared, a
+/// `SharedUpChannel` can be written to concurrently from any number of interrupt priorities or
+/// cores. Each write first reserves space in the buffer with a compare-and-swap, then copies the
+/// data into the reserved space. The data is made visible to the host once all writes in progress
+/// have finished, so the host never sees a partially written message.
+///
+/// Writes never block. In `NoBlockTrim` mode as much of the data as fits is written, and in all
+/// other modes the data is skipped if it doesn't fit in its entirety. Blocking is not supported,
+/// because a write waiting for space could prevent a preempted write from finishing, and thereby
+/// prevent the host from ever making space.
+///
+/// The buffer size is limited to 16 MiB on 32-bit targets, and at most 255 writes can be in
+/// progress at once. Further writes are skipped.
+///
+/// This type is only available on targets with atomic compare-and-swap instructions.
+///
+/// ```
+/// static LOG: SharedUpChannel = SharedUpChannel::new();
+///
+/// let channels = rtt_init! { up: { 0: { size: 1024 } } };
+/// LOG.init(channels.up.0);
+///
+/// // In any context:
+/// LOG.write(b"overcurrent\n");
+/// ```
+pub struct SharedUpChannel {
+    channel: AtomicPtr<RttChannel>,
+    state: AtomicUsize,
+    initialized: AtomicBool,
+}
+
+impl SharedUpChannel {
+    /// Creates a shared channel that discards all writes until it is initialized with
+    /// [`init`](SharedUpChannel::init).
+    pub const fn new() -> Self {
+        SharedUpChannel {
+            channel: AtomicPtr::new(ptr::null_mut()),
+            state: AtomicUsize::new(0),
+            initialized: AtomicBool::new(false),
+        }
+    }
+
+    /// Starts writing to `channel`. This must be called before writing from multiple contexts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer of `channel` is too large for the reservation state, or if the shared
+    /// channel has already been initialized.
+    pub fn init(&self, channel: UpChannel) {
+        let chan = channel.channel();
+        assert!(chan.size() <= POSITION_MASK + 1, "RTT buffer too large");
+
+        // Initializing again would reset the state while writes may be in progress
+        if self
+            .initialized
+            .compare_exchange(false, true, SeqCst, SeqCst)
+            .is_err()
+        {
+            panic!("SharedUpChannel::init must not be called multiple times");
+        }
+
+        self.state.store(chan.read_pointers().0, SeqCst);
+        self.channel.store(channel.channel, SeqCst);
+    }
+
+    /// Writes `buf` to the channel and returns the number of bytes written. Behavior when the
+    /// buffer is full is subject to the channel mode, as described above.
+    pub fn write(&self, buf: &[u8]) -> usize {
+        let chan = self.channel.load(SeqCst);
+        if chan.is_null() || buf.is_empty() {
+            return 0;
+        }
+
+        let chan = unsafe { &*chan };
+        let trim = chan.mode() == ChannelMode::NoBlockTrim;
+
+        // Reserve space by advancing the reservation position past it
+        let mut state = self.state.load(SeqCst);
+        let (pos, len) = loop {
+            let pos = state & POSITION_MASK;
+            let in_flight = state >> IN_FLIGHT_SHIFT;
+
+            let free = chan.free_at(pos);
+            let len = if trim {
+                min(free, buf.len())
+            } else {
+                buf.len()
+            };
+            if len == 0 || len > free || in_flight == MAX_IN_FLIGHT {
+                return 0;
+            }
+
+            let new_state = ((pos + len) % chan.size()) | ((in_flight + 1) << IN_FLIGHT_SHIFT);
+
+            match self
+                .state
+                .compare_exchange_weak(state, new_state, SeqCst, SeqCst)
+            {
+                Ok(_) => break (pos, len),
+                Err(current) => state = current,
+            }
+        };
+
+        // Safety: the region has been reserved for this write only
+        unsafe { chan.copy_to(pos, &buf[..len]) };
+
+        // Finish the write. The last write to finish makes all reserved data visible to the host.
+        // The write pointer is updated before the write is marked as finished, so that no other
+        // write can update it in the meantime, which could make it go backwards.
+        let mut state = self.state.load(SeqCst);
+        loop {
+            if state >> IN_FLIGHT_SHIFT == 1 {
+                chan.publish(state & POSITION_MASK);
+            }
+
+            match self
+                .state
+                .compare_exchange_weak(state, state - IN_FLIGHT_ONE, SeqCst, SeqCst)
+            {
+                Ok(_) => return len,
+                Err(current) => state = current,
+            }
+        }
+    }
+}
+
+impl Default for SharedUpChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,175 @@
+use crate::rtt::RttWriter;
+
+/// The byte that starts a terminal switch sequence or an escaped `0xFF` byte.
+const ESCAPE: u8 = 0xff;
+
+const TERMINAL_ID: [u8; 16] = *b"0123456789ABCDEF";
+
+/// Writes a switch to virtual terminal `number`.
+pub(crate) fn write_switch(writer: &mut RttWriter, number: u8) {
+    writer.write_unsplit(&[ESCAPE, TERMINAL_ID[(number & 0x0f) as usize]]);
+}
+
+/// Writes `bytes` with `0xFF` bytes escaped.
+pub(crate) fn write_escaped(writer: &mut RttWriter, bytes: &[u8]) {
+    let mut chunks = bytes.split(|&b| b == ESCAPE);
+
+    if let Some(chunk) = chunks.next() {
+        writer.write(chunk);
+    }
+
+    for chunk in chunks {
+        writer.write_unsplit(&[ESCAPE, ESCAPE]);
+        writer.write(chunk);
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum DecodeState {
+    Data,
+    Escape,
+}
+
+/// Host side decoder for the data of an up channel written with a
+/// [`TerminalChannel`](crate::TerminalChannel).
+///
+/// The decoder splits the data into the virtual terminals it was written to and removes the
+/// escaping of `0xFF` bytes, as described for [`TerminalChannel`](crate::TerminalChannel).
+/// Sequences may be split across calls to [`decode`](Self::decode), so the data can be fed in as
+/// it is read from the channel. An unknown sequence starting with `0xFF` is passed through
+/// unchanged.
+///
+/// ```
+/// let mut decoder = TerminalDecoder::new();
+///
+/// loop {
+///     let count = read_channel(&mut buf);
+///     decoder.decode(&buf[..count], |terminal, data| outputs[terminal as usize].extend(data));
+/// }
+/// ```
+pub struct TerminalDecoder {
+    state: DecodeState,
+    terminal: u8,
+}
+
+impl TerminalDecoder {
+    /// Creates a decoder starting at virtual terminal 0.
+    pub const fn new() -> Self {
+        TerminalDecoder {
+            state: DecodeState::Data,
+            terminal: 0,
+        }
+    }
+
+    /// Gets the current virtual terminal.
+    pub fn terminal(&self) -> u8 {
+        self.terminal
+    }
+
+    /// Decodes `data`, calling `output` with the virtual terminal number and the decoded bytes for
+    /// each run of data.
+    pub fn decode(&mut self, data: &[u8], mut output: impl FnMut(u8, &[u8])) {
+        let mut start = 0;
+
+        for (i, &byte) in data.iter().enumerate() {
+            match self.state {
+                DecodeState::Data => {
+                    if byte == ESCAPE {
+                        if start < i {
+                            output(self.terminal, &data[start..i]);
+                        }
+
+                        self.state = DecodeState::Escape;
+                    }
+                }
+                DecodeState::Escape => {
+                    if byte == ESCAPE {
+                        output(self.terminal, &[ESCAPE]);
+                    } else if let Some(number) = TERMINAL_ID.iter().position(|&id| id == byte) {
+                        self.terminal = number as u8;
+                    } else {
+                        output(self.terminal, &[ESCAPE, byte]);
+                    }
+
+                    self.state = DecodeState::Data;
+                    start = i + 1;
+                }
+            }
+        }
+
+        if self.state == DecodeState::Data && start < data.len() {
+            output(self.terminal, &data[start..]);
+        }
+    }
+}
+
+impl Default for TerminalDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::rtt::RttChannel;
+    use crate::ChannelMode;
+    use core::ptr;
+    use std::vec::Vec;
+
+    /// Decodes `chunks` in order and collects the output as (terminal, data) runs.
+    fn decode(chunks: &[&[u8]]) -> Vec<(u8, Vec<u8>)> {
+        let mut decoder = TerminalDecoder::new();
+        let mut output: Vec<(u8, Vec<u8>)> = Vec::new();
+
+        for chunk in chunks {
+            decoder.decode(chunk, |terminal, data| match output.last_mut() {
+                Some((last, buf)) if *last == terminal => buf.extend_from_slice(data),
+                _ => output.push((terminal, data.to_vec())),
+            });
+        }
+
+        output
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = [0u8; 64];
+        let mut chan = RttChannel::new();
+        unsafe { chan.init(ptr::null(), ChannelMode::NoBlockSkip, &mut buffer[..]) };
+
+        let data = [b'a', ESCAPE, b'b', ESCAPE, ESCAPE, b'3'];
+        {
+            let mut writer = chan.writer();
+            write_switch(&mut writer, 3);
+            write_escaped(&mut writer, &data);
+        }
+
+        let mut encoded = [0u8; 64];
+        let len = chan.read(&mut encoded);
+
+        assert_eq!(decode(&[&encoded[..len]]), [(3, data.to_vec())]);
+    }
+
+    #[test]
+    fn split_sequences() {
+        let chunks: [&[u8]; 5] = [b"a\xff", b"\xff", b"b\xff", b"1c\xff", b"0d"];
+
+        assert_eq!(
+            decode(&chunks),
+            [
+                (0, b"a\xffb".to_vec()),
+                (1, b"c".to_vec()),
+                (0, b"d".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_escape_passed_through() {
+        assert_eq!(decode(&[b"a\xffzb"]), [(0, b"a\xffzb".to_vec())]);
+        assert_eq!(decode(&[b"a\xff", b"zb"]), [(0, b"a\xffzb".to_vec())]);
+    }
+}
@@ -0,0 +1,108 @@
+use core::cell::Cell;
+use critical_section::Mutex;
+
+/// A source of ticks used for measuring timeouts, such as a cycle counter or a free-running timer.
+///
+/// The tick count is allowed to wrap around. The length of a tick is up to the source, and
+/// timeouts are given in the same unit.
+///
+/// The trait is implemented for closures returning the current tick count, so a cycle counter can
+/// be used directly:
+///
+/// ```
+/// let count = input.read_line_timeout(&mut buf, 8_000_000, || DWT::cycle_count()).count;
+/// ```
+pub trait TickSource {
+    /// Returns the current tick count.
+    fn now(&mut self) -> u32;
+}
+
+impl<F: FnMut() -> u32> TickSource for F {
+    fn now(&mut self) -> u32 {
+        self()
+    }
+}
+
+/// A point in time a given number of ticks after its creation.
+pub(crate) struct Deadline<T: TickSource> {
+    source: T,
+    start: u32,
+    ticks: u32,
+}
+
+impl<T: TickSource> Deadline<T> {
+    pub fn new(mut source: T, ticks: u32) -> Self {
+        let start = source.now();
+
+        Deadline {
+            source,
+            start,
+            ticks,
+        }
+    }
+
+    pub fn expired(&mut self) -> bool {
+        self.source.now().wrapping_sub(self.start) >= self.ticks
+    }
+}
+
+/// Specifies how long a channel in [`BlockWithTimeout`](crate::ChannelMode::BlockWithTimeout) mode
+/// waits for the host to make space in the buffer before giving up. For channels in
+/// [`Adaptive`](crate::ChannelMode::Adaptive) mode, this is the window within which the host must
+/// read from the buffer to be considered connected.
+#[derive(Clone, Copy)]
+pub enum BlockTimeout {
+    /// Give up after checking for space the given number of times. This needs no timer, but the
+    /// actual time depends on the clock speed.
+    Spins(u32),
+
+    /// Give up after `ticks` ticks have passed, as measured by the tick count returned by `now`.
+    /// This can for example be a cycle counter or a free-running timer.
+    Ticks {
+        /// Returns the current tick count. The tick count is allowed to wrap around.
+        now: fn() -> u32,
+
+        /// The timeout in ticks.
+        ticks: u32,
+    },
+}
+
+static BLOCK_TIMEOUT: Mutex<Cell<BlockTimeout>> =
+    Mutex::new(Cell::new(BlockTimeout::Spins(1_000_000)));
+
+/// Sets the timeout for channels in [`BlockWithTimeout`](crate::ChannelMode::BlockWithTimeout)
+/// and [`Adaptive`](crate::ChannelMode::Adaptive) mode. The timeout applies to all channels. The
+/// default is [`BlockTimeout::Spins`] with one million spins.
+pub fn set_block_timeout(timeout: BlockTimeout) {
+    critical_section::with(|cs| BLOCK_TIMEOUT.borrow(cs).set(timeout));
+}
+
+fn block_timeout() -> BlockTimeout {
+    critical_section::with(|cs| BLOCK_TIMEOUT.borrow(cs).get())
+}
+
+/// A running timeout started from the configured [`BlockTimeout`].
+pub(crate) enum Timeout {
+    Spins(u32),
+    Ticks(Deadline<fn() -> u32>),
+}
+
+impl Timeout {
+    pub fn start() -> Self {
+        match block_timeout() {
+            BlockTimeout::Spins(spins) => Timeout::Spins(spins),
+            BlockTimeout::Ticks { now, ticks } => Timeout::Ticks(Deadline::new(now, ticks)),
+        }
+    }
+
+    pub fn expired(&mut self) -> bool {
+        match self {
+            Timeout::Spins(0) => true,
+            Timeout::Spins(spins) => {
+                *spins -= 1;
+                false
+            }
+            Timeout::Ticks(deadline) => deadline.expired(),
+        }
+    }
+}
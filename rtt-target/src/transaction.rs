@@ -0,0 +1,93 @@
+use crate::rtt::RttWriter;
+use crate::terminal;
+use core::convert::Infallible;
+use core::fmt;
+use ufmt_write::uWrite;
+
+/// A write operation made up of multiple writes that is committed or aborted as a whole.
+///
+/// Created with [`UpChannel::transaction`](crate::UpChannel::transaction) or
+/// [`TerminalChannel::transaction`](crate::TerminalChannel::transaction). Data written to the
+/// transaction becomes visible to the host only once it is committed with
+/// [`commit`](Transaction::commit). Dropping the transaction without committing it discards the
+/// data.
+///
+/// The channel mode applies to the transaction as a whole. In `NoBlockSkip` mode, nothing is
+/// committed if any part of the data doesn't fit, and in `NoBlockTrim` mode, everything after the
+/// first part that doesn't fit is discarded. In the blocking modes, data already written has to be
+/// made visible to the host when the buffer fills up, so it can't be aborted anymore.
+///
+/// ```
+/// let mut output = channels.up.0;
+/// let mut transaction = output.transaction();
+/// write!(transaction, "sensor {}: ", id).ok();
+/// transaction.write(&reading.to_le_bytes());
+/// transaction.commit();
+/// ```
+pub struct Transaction<'c> {
+    writer: RttWriter<'c>,
+    terminal: Option<(u8, &'c mut u8)>,
+}
+
+impl<'c> Transaction<'c> {
+    pub(crate) fn new(writer: RttWriter<'c>, terminal: Option<(u8, &'c mut u8)>) -> Self {
+        Transaction { writer, terminal }
+    }
+
+    /// Writes `buf` as part of the transaction. In a transaction on a virtual terminal, `0xFF`
+    /// bytes are escaped as with [`TerminalWriter::write_bytes`](crate::TerminalWriter::write_bytes).
+    pub fn write(&mut self, buf: &[u8]) {
+        if self.terminal.is_some() {
+            terminal::write_escaped(&mut self.writer, buf);
+        } else {
+            self.writer.write(buf);
+        }
+    }
+
+    /// Returns true if data didn't fit in the buffer in `NoBlockSkip` mode, in which case nothing
+    /// will be committed.
+    pub fn is_failed(&self) -> bool {
+        self.writer.is_failed()
+    }
+
+    /// Makes the data written in the transaction visible to the host, and returns the number of
+    /// bytes committed.
+    pub fn commit(mut self) -> usize {
+        let failed = self.writer.is_failed();
+        let count = self.writer.commit();
+
+        if !failed {
+            if let Some((number, current)) = self.terminal.take() {
+                *current = number;
+            }
+        }
+
+        count
+    }
+
+    /// Discards the data written in the transaction.
+    pub fn abort(self) {}
+}
+
+impl fmt::Write for Transaction<'_> {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        self.writer.write_text(s);
+        Ok(())
+    }
+}
+
+impl uWrite for Transaction<'_> {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.writer.write_text(s);
+        Ok(())
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        // Does nothing if the transaction has been committed
+        self.writer.abort();
+    }
+}